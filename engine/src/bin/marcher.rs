@@ -0,0 +1,314 @@
+// Headless entry point, renders a scene straight to a .ppm file
+// Usage: marcher render [--scene default|implicit|file.obj] [--width W] [--height H] [--fov F]
+//                       [--projection perspective|orthographic|fisheye|equirectangular]
+//                       [--integrator whitted|path|spectral|normals|depth|albedo|ao] [--spp N]
+//                       [--pattern grid|jitter|halton|sobol] [--filter box|tent|gaussian|mitchell]
+//                       [--adaptive THRESHOLD] [--max-spp N] [--sample-map file] [--patch-size N]
+//                       [--aperture R] [--focus-distance D] [--blades N] [--lens-samples N]
+//                       [--rig mono|side-by-side|over-under|cube-map] [--interocular D] [--out file]
+// --spp defaults to 16 samples per pixel with the path tracers, 1 with the other integrators
+
+extern crate renderer;
+
+use renderer::camera::Projection;
+use renderer::filter::Filter;
+use renderer::framebuffer;
+use renderer::integrator;
+use renderer::renderer::create_renderer;
+use renderer::rig;
+use renderer::sampling::SamplePattern;
+use renderer::scene::Scene;
+use std::process;
+
+// Samples per pixel when --spp is not given. The path tracers average noisy estimates,
+// the other integrators are deterministic and only need more for anti-aliasing
+const PATH_TRACING_SPP: usize = 16;
+
+const USAGE: &str = "Usage: marcher render [--scene default|implicit|file.obj] [--width W] [--height H] [--fov F] [--projection perspective|orthographic|fisheye|equirectangular] [--integrator whitted|path|spectral|normals|depth|albedo|ao] [--spp N (16 for path and spectral, 1 otherwise)] [--pattern grid|jitter|halton|sobol] [--filter box|tent|gaussian|mitchell] [--adaptive THRESHOLD] [--max-spp N] [--sample-map file.ppm] [--patch-size N] [--aperture R] [--focus-distance D] [--blades N] [--lens-samples N] [--rig mono|side-by-side|over-under|cube-map] [--interocular D] [--out file.ppm]";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    scene: String,
+    width: usize,
+    height: usize,
+    fov: f64,
+    projection: Projection,
+    integrator: String,
+    spp: Option<usize>,
+    pattern: SamplePattern,
+    filter: Filter,
+    adaptive: Option<f64>,
+    max_spp: usize,
+    sample_map: Option<String>,
+    patch_size: usize,
+    aperture: f64,
+    focus_distance: Option<f64>,
+    blades: usize,
+    lens_samples: usize,
+    rig: String,
+    interocular: f64,
+    out: String,
+}
+
+impl Options {
+    fn create_default() -> Options {
+        Options {
+            scene: String::from("default"),
+            width: 1600,
+            height: 1280,
+            fov: 1.5,
+            projection: Projection::Perspective,
+            integrator: String::from("whitted"),
+            spp: None,
+            pattern: SamplePattern::Grid,
+            filter: Filter::Box,
+            adaptive: None,
+            max_spp: 256,
+            sample_map: None,
+            patch_size: 32,
+            aperture: 0.,
+            focus_distance: None,
+            blades: 0,
+            lens_samples: 1,
+            rig: String::from("mono"),
+            interocular: 0.065,
+            out: String::from("out.ppm"),
+        }
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        match (self.spp, self.integrator.as_str()) {
+            (Some(spp), _) => spp,
+            (None, "path") | (None, "spectral") => PATH_TRACING_SPP,
+            (None, _) => 1,
+        }
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    match value {
+        Some(v) => v
+            .parse::<T>()
+            .map_err(|_| format!("Invalid value {} for {}", v, flag)),
+        None => Err(format!("Missing value for {}", flag)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    match args.first().map(|a| a.as_str()) {
+        Some("render") => {}
+        Some(command) => return Err(format!("Unknown command {}", command)),
+        None => return Err(String::from("Missing command")),
+    }
+
+    let mut options = Options::create_default();
+    let mut args = args[1..].iter();
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--scene" => options.scene = parse_value(flag, args.next())?,
+            "--width" => options.width = parse_value(flag, args.next())?,
+            "--height" => options.height = parse_value(flag, args.next())?,
+            "--fov" => options.fov = parse_value(flag, args.next())?,
+            "--projection" => options.projection = parse_value(flag, args.next())?,
+            "--integrator" => options.integrator = parse_value(flag, args.next())?,
+            "--spp" => options.spp = Some(parse_value(flag, args.next())?),
+            "--pattern" => options.pattern = parse_value(flag, args.next())?,
+            "--filter" => options.filter = parse_value(flag, args.next())?,
+            "--adaptive" => options.adaptive = Some(parse_value(flag, args.next())?),
+            "--max-spp" => options.max_spp = parse_value(flag, args.next())?,
+            "--sample-map" => options.sample_map = Some(parse_value(flag, args.next())?),
+            "--patch-size" => options.patch_size = parse_value(flag, args.next())?,
+            "--aperture" => options.aperture = parse_value(flag, args.next())?,
+            "--focus-distance" => options.focus_distance = Some(parse_value(flag, args.next())?),
+            "--blades" => options.blades = parse_value(flag, args.next())?,
+            "--lens-samples" => options.lens_samples = parse_value(flag, args.next())?,
+            "--rig" => options.rig = parse_value(flag, args.next())?,
+            "--interocular" => options.interocular = parse_value(flag, args.next())?,
+            "--out" => options.out = parse_value(flag, args.next())?,
+            _ => return Err(format!("Unknown argument {}", flag)),
+        }
+    }
+
+    if options.width == 0 || options.height == 0 || options.patch_size == 0 {
+        return Err(String::from(
+            "Width, height and patch size need to be positive",
+        ));
+    }
+
+    if !integrator::NAMES.contains(&options.integrator.as_str()) {
+        return Err(format!("Unknown integrator {}", options.integrator));
+    }
+
+    if options.samples_per_pixel() == 0 {
+        return Err(String::from("At least one sample per pixel is needed"));
+    }
+
+    // The cap only applies to adaptive sampling
+    if options.adaptive.is_some() && options.max_spp < options.samples_per_pixel() {
+        return Err(String::from(
+            "The sample cap is below the samples per pixel",
+        ));
+    }
+
+    if options.aperture < 0. || options.focus_distance.is_some_and(|d| d <= 0.) {
+        return Err(String::from(
+            "The aperture cannot be negative, the focus distance needs to be positive",
+        ));
+    }
+
+    if !rig::NAMES.contains(&options.rig.as_str()) {
+        return Err(format!("Unknown rig {}", options.rig));
+    }
+
+    if options.lens_samples == 0 {
+        return Err(String::from("At least one lens sample is needed"));
+    }
+
+    Ok(options)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!["{}\n{}", e, USAGE];
+            process::exit(1);
+        }
+    };
+
+    let aspect = options.width as f64 / options.height as f64;
+    let mut scene = match options.scene.as_str() {
+        "default" => Scene::create_default(),
+        "implicit" => Scene::create_implicit(),
+        _ => match Scene::create_from_obj(options.scene.clone(), options.fov, aspect) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!["{}", e];
+                process::exit(1);
+            }
+        },
+    };
+    scene.camera.vfov = options.fov;
+    scene.camera.aspect = aspect;
+    scene.camera.projection = options.projection;
+    scene.camera.aperture = options.aperture;
+    scene.camera.blades = options.blades;
+    if let Some(distance) = options.focus_distance {
+        scene.camera.focus_distance = distance;
+    }
+
+    let mut fb = framebuffer::create_frame_buffer(options.width, options.height);
+    let mut renderer = create_renderer(options.height as f64, options.width as f64);
+    renderer.integrator = integrator::create(&options.integrator).unwrap();
+    renderer.samples_per_pixel = options.samples_per_pixel();
+    renderer.sample_pattern = options.pattern;
+    renderer.filter = options.filter;
+    renderer.adaptive_threshold = options.adaptive;
+    renderer.max_samples_per_pixel = options.max_spp.max(options.samples_per_pixel());
+    renderer.patch_size = options.patch_size;
+    renderer.lens_samples = options.lens_samples;
+    renderer.rig = rig::create(&options.rig, options.interocular).unwrap();
+
+    let mut sample_counts = framebuffer::create_frame_buffer(options.width, options.height);
+    renderer.render_with_sample_counts(&mut fb, &scene, Some(&mut sample_counts));
+
+    if let Some(ref path) = options.sample_map {
+        if let Err(e) = sample_counts.write_ppm(path) {
+            eprintln!["Could not write {}. Error {:?}", path, e];
+            process::exit(1);
+        }
+        println!["Saved the sample counts to {}", path];
+    }
+
+    fb.normalize();
+    if let Err(e) = fb.write_ppm(&options.out) {
+        eprintln!["Could not write {}. Error {:?}", options.out, e];
+        process::exit(1);
+    }
+    println!["Saved rendered file to {}", options.out];
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        assert_eq![
+            parse_args(&to_args("render")).unwrap(),
+            Options::create_default()
+        ];
+
+        // Path tracing is noisy, take more samples unless told otherwise
+        let samples_per_pixel = |line| parse_args(&to_args(line)).unwrap().samples_per_pixel();
+        assert_eq![samples_per_pixel("render"), 1];
+        assert_eq![samples_per_pixel("render --integrator normals"), 1];
+        assert_eq![samples_per_pixel("render --integrator path"), 16];
+        assert_eq![samples_per_pixel("render --integrator spectral"), 16];
+        assert_eq![samples_per_pixel("render --integrator path --spp 1"), 1];
+
+        // No cap without adaptive sampling
+        assert_eq![samples_per_pixel("render --integrator path --spp 512"), 512];
+        assert_eq![samples_per_pixel("render --spp 16 --max-spp 8"), 16];
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let options = parse_args(&to_args(
+            "render --scene box.obj --width 641 --height 479 --fov 1.2 --projection fisheye --integrator path --spp 64 --pattern sobol --filter mitchell --adaptive 0.05 --max-spp 512 --sample-map spp.ppm --patch-size 16 --aperture 0.1 --focus-distance 4.5 --blades 6 --lens-samples 8 --rig over-under --interocular 0.1 --out box.ppm",
+        ))
+        .unwrap();
+
+        assert_eq![options.scene, "box.obj"];
+        assert_eq![options.width, 641];
+        assert_eq![options.height, 479];
+        assert_eq![options.fov, 1.2];
+        assert_eq![options.projection, Projection::Fisheye];
+        assert_eq![options.integrator, "path"];
+        assert_eq![options.samples_per_pixel(), 64];
+        assert_eq![options.pattern, SamplePattern::Sobol];
+        assert_eq![options.filter, Filter::Mitchell];
+        assert_eq![options.adaptive, Some(0.05)];
+        assert_eq![options.max_spp, 512];
+        assert_eq![options.patch_size, 16];
+        assert_eq![options.sample_map, Some(String::from("spp.ppm"))];
+        assert_eq![options.aperture, 0.1];
+        assert_eq![options.focus_distance, Some(4.5)];
+        assert_eq![options.blades, 6];
+        assert_eq![options.lens_samples, 8];
+        assert_eq![options.rig, "over-under"];
+        assert_eq![options.interocular, 0.1];
+        assert_eq![options.out, "box.ppm"];
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert![parse_args(&to_args("")).is_err()];
+        assert![parse_args(&to_args("draw")).is_err()];
+        assert![parse_args(&to_args("render --width")).is_err()];
+        assert![parse_args(&to_args("render --width abc")).is_err()];
+        assert![parse_args(&to_args("render --height 0")).is_err()];
+        assert![parse_args(&to_args("render --patch-size 0")).is_err()];
+        assert![parse_args(&to_args("render --colour red")).is_err()];
+        assert![parse_args(&to_args("render --integrator photons")).is_err()];
+        assert![parse_args(&to_args("render --spp 0")).is_err()];
+        assert![parse_args(&to_args("render --filter lanczos")).is_err()];
+        assert![parse_args(&to_args("render --projection cylindrical")).is_err()];
+        assert![parse_args(&to_args("render --adaptive 0.1 --spp 16 --max-spp 8")).is_err()];
+        assert![parse_args(&to_args(
+            "render --adaptive 0.1 --integrator path --max-spp 8"
+        ))
+        .is_err()];
+        assert![parse_args(&to_args("render --aperture -1")).is_err()];
+        assert![parse_args(&to_args("render --focus-distance 0")).is_err()];
+        assert![parse_args(&to_args("render --lens-samples 0")).is_err()];
+        assert![parse_args(&to_args("render --rig quad")).is_err()];
+    }
+}
@@ -7,18 +7,11 @@ extern crate relm_derive;
 extern crate gdk;
 extern crate gdk_pixbuf;
 extern crate gtk;
+extern crate renderer;
 
-mod framebuffer;
-mod geometry;
-mod lights;
-mod obj;
-mod optics;
-mod polygon;
-mod renderer;
-mod scene;
-mod shapes;
-mod sphere;
-mod triangle;
+use renderer::framebuffer;
+use renderer::geometry;
+use renderer::scene;
 
 use gdk_pixbuf::Pixbuf;
 use gtk::prelude::*;
@@ -267,58 +260,20 @@ impl Win {
                     self.new_renderer()
                 }
 
-                // Load the file
-                let objects = obj::load(filepath);
+                // Load the file, add all the objects to the render scene
+                let aspect = self.fb.width as f64 / self.fb.height as f64;
+                match scene::Scene::create_from_obj(filepath, self.scene.camera.vfov, aspect) {
+                    Ok(scene) => {
+                        println!["Opened file successfuly"];
+                        self.scene = scene;
 
-                // Add all the objects to the render scene
-                let mut scene = scene::Scene::new();
-
-                if let Some(objects) = objects {
-                    // Default offset, move the scene to the back
-                    let offset = geometry::Vec3f {
-                        x: 0.,
-                        y: 0.,
-                        z: -500.,
-                    };
-
-                    for mut obj in objects {
-                        obj.offset(offset);
-                        // `Box` moves storage to the heap
-                        scene.shapes.push(std::boxed::Box::new(obj));
+                        // Re-run the raytracer
+                        self.update_raytrace_image();
+                    }
+                    Err(e) => {
+                        println!["Failed opening .obj file. Error {}", e];
                     }
                 }
-
-                // Add an arbitrary set of lights
-                // FIXME: Need something a tiny bit better
-                scene.lights.push(lights::create_light(
-                    geometry::Vec3f {
-                        x: 0.,
-                        y: 0.,
-                        z: 0.,
-                    },
-                    geometry::Vec3f::ones(), // white light
-                    1.,
-                ));
-
-                scene.lights.push(lights::create_light(
-                    geometry::Vec3f {
-                        x: 20.,
-                        y: 20.,
-                        z: 20.,
-                    },
-                    geometry::Vec3f {
-                        x: 1.,
-                        y: 0.5,
-                        z: 0.5,
-                    }, // reddish light
-                    0.8,
-                ));
-
-                println!["Opened file successfuly"];
-                self.scene = scene;
-
-                // Re-run the raytracer
-                self.update_raytrace_image();
             }
             Err(e) => {
                 println!["Filed opening .obj file. Error {:?}", e];
@@ -329,6 +284,7 @@ impl Win {
     fn update_raytrace_image(&mut self) {
         if self.model.started_rendering.is_some() {
             let raymarcher = self.model.started_rendering.as_mut().unwrap();
+            self.scene.camera.aspect = self.fb.width as f64 / self.fb.height as f64;
             self.state_label
                 .set_text(&raymarcher.render(&mut self.fb, &self.scene).to_string());
 
@@ -365,7 +321,7 @@ impl Win {
         // Create the renderer
         // FIXME: the fb is only use for sizing purposes, should be cleaned
         let ray_marcher =
-            renderer::create_renderer(1.5, self.fb.height as f64, self.fb.width as f64);
+            renderer::renderer::create_renderer(self.fb.height as f64, self.fb.width as f64);
         self.model.started_rendering = Some(ray_marcher);
         self.state_label.set_text("Created new ray tracing engine");
     }
@@ -0,0 +1,518 @@
+use geometry::Vec3f;
+use shapes::{BoundingBox, Intersection, Shape};
+
+// Bounding volume hierarchy over a set of bounding boxes, used to cull
+// the intersection tests. The tree is stored flat, leaves refer to a range
+// of the re-ordered primitive indices.
+
+const MAX_LEAF_SIZE: usize = 2;
+
+// Surface area heuristic parameters, see http://www.pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies.html
+const SAH_BINS: usize = 12;
+const SAH_TRAVERSAL_COST: f64 = 0.5; // Relative to one primitive intersection
+const SAH_MAX_LEAF_SIZE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Split {
+    Median, // Fast to build, fine for a handful of shapes
+    Sah,    // Slower to build, much faster to traverse on big meshes
+}
+
+#[derive(Clone, Debug)]
+enum Content {
+    Leaf { start: usize, end: usize },
+    Inner { left: usize, right: usize },
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    bounding_box: BoundingBox,
+    content: Content,
+}
+
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+fn axis_value(vec: &Vec3f, axis: usize) -> f64 {
+    match axis {
+        0 => vec.x,
+        1 => vec.y,
+        _ => vec.z,
+    }
+}
+
+impl Bvh {
+    pub fn create(boxes: &[BoundingBox]) -> Bvh {
+        Bvh::create_with_split(boxes, Split::Median)
+    }
+
+    pub fn create_with_split(boxes: &[BoundingBox], split: Split) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * boxes.len()),
+            indices: (0..boxes.len()).collect(),
+        };
+
+        if !boxes.is_empty() {
+            let centers: Vec<Vec3f> = boxes.iter().map(|b| b.middle()).collect();
+            bvh.build(boxes, &centers, split, 0, boxes.len());
+        }
+        bvh
+    }
+
+    pub fn create_from_shapes(shapes: &[Box<dyn Shape + Sync>]) -> Bvh {
+        let boxes: Vec<BoundingBox> = shapes.iter().map(|s| s.bounding_box()).collect();
+        Bvh::create(&boxes)
+    }
+
+    // Recursively build the node covering indices[start..end], returns its index
+    fn build(
+        &mut self,
+        boxes: &[BoundingBox],
+        centers: &[Vec3f],
+        split: Split,
+        start: usize,
+        end: usize,
+    ) -> usize {
+        let mut bounding_box = boxes[self.indices[start]].clone();
+        let mut centers_box = BoundingBox::create(centers[self.indices[start]]);
+        for i in &self.indices[start..end] {
+            bounding_box.merge(&boxes[*i]);
+            centers_box.update(&centers[*i]);
+        }
+
+        let middle = match split {
+            Split::Median => self.split_median(centers, &centers_box, start, end),
+            Split::Sah => self.split_sah(boxes, centers, &bounding_box, &centers_box, start, end),
+        };
+
+        let node_index = self.nodes.len();
+        self.nodes.push(Node {
+            bounding_box,
+            content: Content::Leaf { start, end },
+        });
+
+        let middle = match middle {
+            Some(middle) => middle,
+            None => return node_index, // Not worth splitting, keep the leaf
+        };
+
+        let left = self.build(boxes, centers, split, start, middle);
+        let right = self.build(boxes, centers, split, middle, end);
+        self.nodes[node_index].content = Content::Inner { left, right };
+        node_index
+    }
+
+    // Split indices[start..end] in two halves along the axis where the
+    // primitives are the most spread out, returns the split position
+    fn split_median(
+        &mut self,
+        centers: &[Vec3f],
+        centers_box: &BoundingBox,
+        start: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let extent = centers_box.max - centers_box.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        // Small enough, or all the primitives are at the same spot
+        if end - start <= MAX_LEAF_SIZE || axis_value(&extent, axis) <= 0. {
+            return None;
+        }
+
+        let middle = (start + end) / 2;
+        self.indices[start..end].select_nth_unstable_by(middle - start, |a, b| {
+            axis_value(&centers[*a], axis)
+                .partial_cmp(&axis_value(&centers[*b], axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Some(middle)
+    }
+
+    // Binned surface area heuristic: bucket the primitives along each axis,
+    // pick the bucket boundary minimizing the expected traversal cost
+    fn split_sah(
+        &mut self,
+        boxes: &[BoundingBox],
+        centers: &[Vec3f],
+        node_box: &BoundingBox,
+        centers_box: &BoundingBox,
+        start: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let count = end - start;
+        if count <= 1 {
+            return None;
+        }
+
+        let extent = centers_box.max - centers_box.min;
+        let bin_of = |center: &Vec3f, axis: usize| {
+            let offset = axis_value(&(*center - centers_box.min), axis) / axis_value(&extent, axis);
+            ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+        };
+
+        // (cost, axis, last bin on the left side)
+        let mut best: Option<(f64, usize, usize)> = None;
+
+        for axis in 0..3 {
+            if axis_value(&extent, axis) <= 0. {
+                continue;
+            }
+
+            let mut bin_counts = [0; SAH_BINS];
+            let mut bin_boxes: Vec<Option<BoundingBox>> = vec![None; SAH_BINS];
+            for i in &self.indices[start..end] {
+                let b = bin_of(&centers[*i], axis);
+                bin_counts[b] += 1;
+                match bin_boxes[b] {
+                    Some(ref mut bb) => bb.merge(&boxes[*i]),
+                    None => bin_boxes[b] = Some(boxes[*i].clone()),
+                }
+            }
+
+            // Sweep from the right to get the area and count of every right side
+            let mut right_costs = [0.; SAH_BINS];
+            let mut right_box: Option<BoundingBox> = None;
+            let mut right_count = 0;
+            for b in (1..SAH_BINS).rev() {
+                right_count += bin_counts[b];
+                accumulate(&mut right_box, &bin_boxes[b]);
+                right_costs[b - 1] = surface_area(&right_box) * right_count as f64;
+            }
+
+            // Sweep from the left, evaluate every split
+            let mut left_box: Option<BoundingBox> = None;
+            let mut left_count = 0;
+            for b in 0..SAH_BINS - 1 {
+                left_count += bin_counts[b];
+                accumulate(&mut left_box, &bin_boxes[b]);
+                if left_count == 0 || left_count == count {
+                    continue;
+                }
+
+                let cost = surface_area(&left_box) * left_count as f64 + right_costs[b];
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        let (cost, axis, split_bin) = best?;
+
+        // Compare with the cost of intersecting all the primitives in this node
+        let node_area = surface_area(&Some(node_box.clone()));
+        let split_cost = if node_area > 0. {
+            SAH_TRAVERSAL_COST + cost / node_area
+        } else {
+            SAH_TRAVERSAL_COST
+        };
+
+        if split_cost >= count as f64 && count <= SAH_MAX_LEAF_SIZE {
+            return None;
+        }
+
+        // Partition the primitives in place
+        let mut middle = start;
+        for k in start..end {
+            if bin_of(&centers[self.indices[k]], axis) <= split_bin {
+                self.indices.swap(k, middle);
+                middle += 1;
+            }
+        }
+        Some(middle)
+    }
+
+    // Closest hit in between the ray and the primitives, `intersect` being
+    // called with the index of each primitive which bounding box is hit.
+    // Returns the intersection and the index of the primitive
+    pub fn closest_hit<F>(
+        &self,
+        orig: &Vec3f,
+        dir: &Vec3f,
+        intersect: F,
+    ) -> Option<(Intersection, usize)>
+    where
+        F: Fn(usize) -> Option<Intersection>,
+    {
+        let mut closest: Option<(Intersection, usize)> = None;
+        let mut dist_closest = f64::INFINITY;
+
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            match node.bounding_box.intersect(orig, dir) {
+                Some(dist) if dist <= dist_closest => {}
+                _ => continue,
+            }
+
+            match node.content {
+                Content::Leaf { start, end } => {
+                    for i in &self.indices[start..end] {
+                        if let Some(intersection) = intersect(*i) {
+                            let dist_hit = (intersection.point - *orig).squared_norm().sqrt();
+                            if dist_hit < dist_closest {
+                                dist_closest = dist_hit;
+                                closest = Some((intersection, *i));
+                            }
+                        }
+                    }
+                }
+                Content::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        closest
+    }
+
+    // Check whether the ray hits *any* primitive, useful for shadows
+    pub fn any_hit<F>(&self, orig: &Vec3f, dir: &Vec3f, intersect: F) -> bool
+    where
+        F: Fn(usize) -> bool,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            if node.bounding_box.intersect(orig, dir).is_none() {
+                continue;
+            }
+
+            match node.content {
+                Content::Leaf { start, end } => {
+                    if self.indices[start..end].iter().any(|i| intersect(*i)) {
+                        return true;
+                    }
+                }
+                Content::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        false
+    }
+
+    // Move all the boxes, cheaper than rebuilding the tree
+    pub fn offset(&mut self, off: Vec3f) {
+        for node in &mut self.nodes {
+            node.bounding_box.min += off;
+            node.bounding_box.max += off;
+        }
+    }
+}
+
+fn accumulate(acc: &mut Option<BoundingBox>, other: &Option<BoundingBox>) {
+    if let Some(other) = other {
+        match acc {
+            Some(acc) => acc.merge(other),
+            None => *acc = Some(other.clone()),
+        }
+    }
+}
+
+fn surface_area(bb: &Option<BoundingBox>) -> f64 {
+    match bb {
+        Some(bb) => {
+            let d = bb.max - bb.min;
+            2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+        }
+        None => 0.,
+    }
+}
+
+// ************************************************************
+// Accelerated versions of the shapes:: set queries
+// ************************************************************
+
+pub fn find_closest_intersect(
+    orig: &Vec3f,
+    dir: Vec3f,
+    shapes: &[Box<dyn Shape + Sync>],
+    bvh: &Bvh,
+) -> Option<(Intersection, usize)> {
+    bvh.closest_hit(orig, &dir, |i| shapes[i].intersect(orig, &dir))
+}
+
+pub fn intersect_shape_set(
+    orig: &Vec3f,
+    dir: &Vec3f,
+    shapes: &[Box<dyn Shape + Sync>],
+    bvh: &Bvh,
+) -> bool {
+    bvh.any_hit(orig, dir, |i| shapes[i].intersect(orig, dir).is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shapes::Reflectance;
+    use sphere;
+
+    fn sphere_grid(n: usize) -> Vec<Box<dyn Shape + Sync>> {
+        let mut shapes: Vec<Box<dyn Shape + Sync>> = vec![];
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    shapes.push(Box::new(sphere::create(
+                        Vec3f {
+                            x: 3. * i as f64 - 10.,
+                            y: 3. * j as f64 - 10.,
+                            z: -3. * k as f64 - 5.,
+                        },
+                        0.5 + 0.1 * ((i + j + k) % 5) as f64,
+                        Reflectance::create_default(),
+                    )));
+                }
+            }
+        }
+        shapes
+    }
+
+    #[test]
+    fn test_box_intersect() {
+        let bb = BoundingBox {
+            min: Vec3f {
+                x: -1.,
+                y: -1.,
+                z: -3.,
+            },
+            max: Vec3f {
+                x: 1.,
+                y: 1.,
+                z: -2.,
+            },
+        };
+
+        let orig = Vec3f::zero();
+        let forward = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+
+        assert_eq![bb.intersect(&orig, &forward), Some(2.)];
+        assert_eq![bb.intersect(&orig, &-forward), None];
+
+        // Starting from the inside
+        let inside = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -2.5,
+        };
+        assert_eq![bb.intersect(&inside, &forward), Some(0.)];
+
+        // Flat box, seen from the side and from the top
+        let flat = BoundingBox {
+            min: Vec3f {
+                x: -1.,
+                y: -1.,
+                z: -2.,
+            },
+            max: Vec3f {
+                x: 1.,
+                y: 1.,
+                z: -2.,
+            },
+        };
+        assert_eq![flat.intersect(&orig, &forward), Some(2.)];
+        let side = Vec3f {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        assert![flat.intersect(&orig, &side).is_none()];
+    }
+
+    // Reference queries, every shape is tested
+    fn linear_closest_intersect(
+        orig: &Vec3f,
+        dir: Vec3f,
+        shapes: &[Box<dyn Shape + Sync>],
+    ) -> Option<(Intersection, usize)> {
+        let mut closest: Option<(Intersection, usize)> = None;
+        for (index, shape) in shapes.iter().enumerate() {
+            if let Some(intersection) = shape.intersect(orig, &dir) {
+                let dist = (intersection.point - *orig).squared_norm();
+                match closest {
+                    Some((ref c, _)) if (c.point - *orig).squared_norm() <= dist => {}
+                    _ => closest = Some((intersection, index)),
+                }
+            }
+        }
+        closest
+    }
+
+    fn linear_intersect_any(orig: &Vec3f, dir: &Vec3f, shapes: &[Box<dyn Shape + Sync>]) -> bool {
+        shapes
+            .iter()
+            .any(|shape| shape.intersect(orig, dir).is_some())
+    }
+
+    #[test]
+    fn test_bvh_matches_linear() {
+        let shapes = sphere_grid(6);
+        let bvh = Bvh::create_from_shapes(&shapes);
+
+        for i in 0..40 {
+            for j in 0..40 {
+                let orig = Vec3f {
+                    x: 0.,
+                    y: 0.,
+                    z: 2.,
+                };
+                let dir = Vec3f {
+                    x: i as f64 / 20. - 1.,
+                    y: j as f64 / 20. - 1.,
+                    z: -1.,
+                }
+                .normalized();
+
+                let linear = linear_closest_intersect(&orig, dir, &shapes);
+                let accelerated = find_closest_intersect(&orig, dir, &shapes, &bvh);
+
+                assert_eq![linear.is_some(), accelerated.is_some()];
+                if let (Some(l), Some(a)) = (linear, accelerated) {
+                    assert![(l.0.point - a.0.point).squared_norm() < 1e-8];
+                    assert_eq![l.1, a.1];
+                }
+
+                assert_eq![
+                    linear_intersect_any(&orig, &dir, &shapes),
+                    intersect_shape_set(&orig, &dir, &shapes, &bvh)
+                ];
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_bvh() {
+        let shapes: Vec<Box<dyn Shape + Sync>> = vec![];
+        let bvh = Bvh::create_from_shapes(&shapes);
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        assert![find_closest_intersect(&Vec3f::zero(), dir, &shapes, &bvh).is_none()];
+        assert![!intersect_shape_set(&Vec3f::zero(), &dir, &shapes, &bvh)];
+    }
+}
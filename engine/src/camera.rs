@@ -0,0 +1,328 @@
+use geometry::Vec3f;
+use sampling;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// How the picture maps to the viewing directions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,     // Pinhole, straight lines stay straight
+    Orthographic,    // Parallel rays, the focus plane keeps its perspective size
+    Fisheye,         // Equidistant, the angle to the axis grows linearly with the radius
+    Equirectangular, // Full 360 x 180 degrees panorama, longitude and latitude
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Projection, String> {
+        match name {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            _ => Err(format!("Unknown projection {}", name)),
+        }
+    }
+}
+
+// Camera looking from a position towards a target. Pinhole by default,
+// a thin lens with some depth of field when the aperture is not zero
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub position: Vec3f,
+    pub target: Vec3f,
+    pub up: Vec3f,   // Does not need to be orthogonal to the viewing direction
+    pub vfov: f64,   // Vertical field of view, in radians. Fisheye: vertical angle covered
+    pub aspect: f64, // Width over height of the picture
+    pub projection: Projection,
+
+    pub aperture: f64,       // Lens radius, in scene units
+    pub focus_distance: f64, // Along the viewing direction, sharp plane
+    pub blades: usize,       // Polygonal diaphragm (bokeh shape), round below 3
+}
+
+impl Camera {
+    pub fn create(position: Vec3f, target: Vec3f, up: Vec3f, vfov: f64, aspect: f64) -> Camera {
+        // Focus on the target by default
+        Camera {
+            position,
+            target,
+            up,
+            vfov,
+            aspect,
+            projection: Projection::Perspective,
+            aperture: 0.,
+            focus_distance: (target - position).norm(),
+            blades: 0,
+        }
+    }
+
+    // At the origin, looking down -z
+    pub fn create_default() -> Camera {
+        Camera::create(
+            Vec3f::zero(),
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            1.5,
+            1.,
+        )
+    }
+
+    // Orthonormal basis: right, up and forward (viewing direction)
+    pub fn basis(&self) -> (Vec3f, Vec3f, Vec3f) {
+        let forward = (self.target - self.position).normalized();
+        let mut right = forward.cross(self.up).normalized();
+
+        // Up vector aligned with the viewing direction, pick any right vector
+        if right.squared_norm() < 0.5 {
+            let helper = if forward.x.abs() < 0.9 {
+                Vec3f {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                }
+            } else {
+                Vec3f {
+                    x: 0.,
+                    y: 0.,
+                    z: 1.,
+                }
+            };
+            right = forward.cross(helper).normalized();
+        }
+
+        let up = right.cross(forward);
+        (right, up, forward)
+    }
+
+    // Ray direction through a point of the picture, from (0, 0) on the top left corner
+    // to (1, 1) on the bottom right corner
+    pub fn direction(&self, x: f64, y: f64) -> Vec3f {
+        let (right, up, forward) = self.basis();
+        let half_height = (self.vfov / 2.).tan();
+        let half_width = half_height * self.aspect;
+
+        (forward
+            + right.scaled((2. * x - 1.) * half_width)
+            + up.scaled((1. - 2. * y) * half_height))
+        .normalized()
+    }
+
+    // Ray through the center of the lens, None outside of the fisheye circle
+    fn pinhole_ray(&self, x: f64, y: f64) -> Option<(Vec3f, Vec3f)> {
+        let (right, up, forward) = self.basis();
+        let (px, py) = ((2. * x - 1.) * self.aspect, 1. - 2. * y);
+
+        match self.projection {
+            Projection::Perspective => Some((self.position, self.direction(x, y))),
+            Projection::Orthographic => {
+                let half_height = self.focus_distance * (self.vfov / 2.).tan();
+                let origin =
+                    self.position + right.scaled(px * half_height) + up.scaled(py * half_height);
+                Some((origin, forward))
+            }
+            Projection::Fisheye => {
+                // Circular image, touching the top and bottom of the picture
+                let radius = (px * px + py * py).sqrt();
+                let theta = radius * self.vfov / 2.;
+                if radius > 1. || theta > PI {
+                    return None;
+                }
+                if radius < 1e-12 {
+                    return Some((self.position, forward));
+                }
+                let side = (right.scaled(px) + up.scaled(py)).scaled(theta.sin() / radius);
+                Some((
+                    self.position,
+                    (forward.scaled(theta.cos()) + side).normalized(),
+                ))
+            }
+            Projection::Equirectangular => {
+                let longitude = (2. * x - 1.) * PI;
+                let latitude = (1. - 2. * y) * PI / 2.;
+                let dir = forward.scaled(latitude.cos() * longitude.cos())
+                    + right.scaled(latitude.cos() * longitude.sin())
+                    + up.scaled(latitude.sin());
+                Some((self.position, dir.normalized()))
+            }
+        }
+    }
+
+    // Primary ray through a point of the picture, origin and direction.
+    // (u1, u2) in [0, 1[ pick the position on the lens. The fisheye and panoramic
+    // projections ignore the lens, and leave the pixels outside of the fisheye circle empty
+    pub fn ray(&self, x: f64, y: f64, u1: f64, u2: f64) -> Option<(Vec3f, Vec3f)> {
+        let (origin, dir) = self.pinhole_ray(x, y)?;
+        let thin_lens = match self.projection {
+            Projection::Perspective | Projection::Orthographic => self.aperture > 0.,
+            Projection::Fisheye | Projection::Equirectangular => false,
+        };
+        if !thin_lens {
+            return Some((origin, dir));
+        }
+
+        // All the rays through the lens converge on the focus plane
+        let (right, up, forward) = self.basis();
+        let focus_point = origin + dir.scaled(self.focus_distance / dir.dot(forward));
+
+        let (lx, ly) = sampling::uniform_polygon(self.blades, u1, u2);
+        let origin = origin + right.scaled(lx * self.aperture) + up.scaled(ly * self.aperture);
+        Some((origin, (focus_point - origin).normalized()))
+    }
+
+    // Move the camera, keeps looking in the same direction
+    pub fn offset(&mut self, offset: Vec3f) {
+        self.position += offset;
+        self.target += offset;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vec(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    fn close(a: Vec3f, b: Vec3f) -> bool {
+        (a - b).norm() < 1e-9
+    }
+
+    #[test]
+    fn test_default_camera() {
+        let camera = Camera::create_default();
+        let (right, up, forward) = camera.basis();
+        assert![close(right, vec(1., 0., 0.))];
+        assert![close(up, vec(0., 1., 0.))];
+        assert![close(forward, vec(0., 0., -1.))];
+
+        // Center, and the top edge at half the vertical field of view
+        assert![close(camera.direction(0.5, 0.5), vec(0., 0., -1.))];
+        let top = camera.direction(0.5, 0.);
+        assert![(top.y.atan2(-top.z) - 0.75).abs() < 1e-9];
+        assert![camera.direction(0., 1.).x < 0.];
+        assert![camera.direction(0., 1.).y < 0.];
+    }
+
+    #[test]
+    fn test_look_at() {
+        // Looking towards +z from below, tilted up vector
+        let camera = Camera::create(
+            vec(1., -2., -10.),
+            vec(1., 3., 10.),
+            vec(0.2, 1., 0.),
+            1.,
+            2.,
+        );
+        let (right, up, forward) = camera.basis();
+
+        assert![close(forward, vec(0., 5., 20.).normalized())];
+        assert![right.dot(forward).abs() < 1e-9 && up.dot(forward).abs() < 1e-9];
+        assert![right.dot(up).abs() < 1e-9];
+        assert![up.y > 0.];
+        assert![close(camera.direction(0.5, 0.5), forward)];
+
+        // Horizontal field of view follows the aspect ratio
+        let half_width = (0.5f64).tan() * 2.;
+        let edge = camera.direction(1., 0.5);
+        assert![(edge.dot(right) / edge.dot(forward) - half_width).abs() < 1e-9];
+
+        // Degenerate up vector, still a valid basis
+        let camera = Camera::create(Vec3f::zero(), vec(0., 5., 0.), vec(0., 1., 0.), 1., 1.);
+        let (right, up, forward) = camera.basis();
+        assert![(right.norm() - 1.).abs() < 1e-9 && (up.norm() - 1.).abs() < 1e-9];
+        assert![right.dot(forward).abs() < 1e-9 && up.dot(forward).abs() < 1e-9];
+    }
+
+    #[test]
+    fn test_offset() {
+        let mut camera = Camera::create_default();
+        camera.offset(vec(1., 2., 3.));
+        assert_eq![camera.position, vec(1., 2., 3.)];
+        assert![close(camera.direction(0.5, 0.5), vec(0., 0., -1.))];
+    }
+
+    #[test]
+    fn test_thin_lens() {
+        let mut camera = Camera::create_default();
+        assert_eq![camera.focus_distance, 1.];
+
+        // Pinhole, the lens position does not matter
+        let (origin, dir) = camera.ray(0.2, 0.7, 0.9, 0.1).unwrap();
+        assert_eq![origin, Vec3f::zero()];
+        assert![close(dir, camera.direction(0.2, 0.7))];
+
+        // Rays spread over the lens, and meet again on the focus plane
+        camera.aperture = 0.5;
+        camera.focus_distance = 4.;
+        camera.blades = 5;
+        let center = camera.direction(0.2, 0.7);
+        let focus_point = center.scaled(4. / -center.z);
+
+        for (u1, u2) in [(0.1, 0.2), (0.5, 0.9), (0.99, 0.01), (0.7, 0.6)].iter() {
+            let (origin, dir) = camera.ray(0.2, 0.7, *u1, *u2).unwrap();
+            assert![origin.z == 0. && origin.norm() <= 0.5 + 1e-12];
+            let t = (focus_point - origin).norm();
+            assert![close(origin + dir.scaled(t), focus_point)];
+        }
+    }
+
+    #[test]
+    fn test_projections() {
+        let mut camera = Camera::create(vec(0., 0., 0.), vec(0., 0., -4.), vec(0., 1., 0.), 1., 2.);
+
+        // Orthographic: parallel rays, same footprint as the perspective on the focus plane
+        camera.projection = Projection::Orthographic;
+        let (origin, dir) = camera.ray(1., 0., 0.5, 0.5).unwrap();
+        assert![close(dir, vec(0., 0., -1.))];
+        let corner = camera.direction(1., 0.);
+        assert![close(
+            origin,
+            corner.scaled(4. / -corner.z) + vec(0., 0., 4.)
+        )];
+
+        // Fisheye: the angle to the axis grows linearly, nothing past the full sphere
+        camera.projection = Projection::Fisheye;
+        camera.vfov = PI;
+        let angle = |x: f64, y: f64| camera.ray(x, y, 0.5, 0.5).unwrap().1.dot(vec(0., 0., -1.));
+        assert![(angle(0.5, 0.5) - 1.).abs() < 1e-9];
+        assert![(angle(0.5, 0.75) - (PI / 4.).cos()).abs() < 1e-9];
+        assert![angle(0.5, 1.).abs() < 1e-9];
+        assert![(angle(0.75, 0.5) - (PI / 2.).cos()).abs() < 1e-9];
+        assert![camera.ray(0., 0., 0.5, 0.5).is_none()];
+
+        // The corners are out of the image circle, even with a narrower field of view
+        camera.vfov = 1.5;
+        let aspect = camera.aspect;
+        camera.aspect = 1.;
+        assert![camera.ray(0., 0., 0.5, 0.5).is_none()];
+        assert![camera.ray(1., 1., 0.5, 0.5).is_none()];
+        assert![camera.ray(0.5, 0.001, 0.5, 0.5).is_some()];
+        assert![camera.ray(0.001, 0.5, 0.5, 0.5).is_some()];
+        camera.aspect = aspect;
+        camera.vfov = PI;
+
+        // Equirectangular: longitude along x, latitude along y, whatever the field of view
+        camera.projection = Projection::Equirectangular;
+        let dir = |x: f64, y: f64| camera.ray(x, y, 0.5, 0.5).unwrap().1;
+        assert![close(dir(0.5, 0.5), vec(0., 0., -1.))];
+        assert![close(dir(0.75, 0.5), vec(1., 0., 0.))];
+        assert![close(dir(0.25, 0.5), vec(-1., 0., 0.))];
+        assert![close(dir(0., 0.5), vec(0., 0., 1.))];
+        assert![close(dir(0.3, 0.), vec(0., 1., 0.))];
+        assert![close(dir(0.5, 0.75), vec(0., -1., -1.).normalized())];
+
+        assert_eq!["fisheye".parse::<Projection>(), Ok(Projection::Fisheye)];
+        assert!["cylindrical".parse::<Projection>().is_err()];
+    }
+}
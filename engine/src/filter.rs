@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+// Pixel reconstruction filters, weighting the samples by their offset to the pixel center.
+// Offsets and radii are expressed in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box,      // Plain average over the pixel
+    Tent,     // Linear falloff, one pixel away
+    Gaussian, // Sigma of half a pixel, truncated at 1.5 pixels
+    Mitchell, // Mitchell-Netravali, B = C = 1/3. Slightly negative lobes, sharper
+}
+
+const GAUSSIAN_SIGMA: f64 = 0.5;
+
+fn gaussian(x: f64, radius: f64) -> f64 {
+    // Shifted so that the weight smoothly reaches zero at the radius
+    let g = |x: f64| (-x * x / (2. * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+    (g(x) - g(radius)).max(0.)
+}
+
+fn mitchell(x: f64) -> f64 {
+    // See "Reconstruction filters in computer graphics", Mitchell and Netravali, 1988
+    let (b, c) = (1. / 3., 1. / 3.);
+    let x = x.abs();
+    if x < 1. {
+        ((12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b))
+            / 6.
+    } else if x < 2. {
+        ((-b - 6. * c) * x * x * x
+            + (6. * b + 30. * c) * x * x
+            + (-12. * b - 48. * c) * x
+            + (8. * b + 24. * c))
+            / 6.
+    } else {
+        0.
+    }
+}
+
+impl Filter {
+    // Half width of the support
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+
+    // Separable filters, not normalized
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        let radius = self.radius();
+        if dx.abs() > radius || dy.abs() > radius {
+            return 0.;
+        }
+
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => (1. - dx.abs()) * (1. - dy.abs()),
+            Filter::Gaussian => gaussian(dx, radius) * gaussian(dy, radius),
+            Filter::Mitchell => mitchell(dx) * mitchell(dy),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Filter, String> {
+        match name {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("Unknown filter {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let filters = [
+            Filter::Box,
+            Filter::Tent,
+            Filter::Gaussian,
+            Filter::Mitchell,
+        ];
+
+        for filter in filters.iter() {
+            // Peak at the center, symmetric, nothing outside of the support
+            let center = filter.weight(0., 0.);
+            assert![center > 0.];
+            assert![filter.weight(0.3, 0.2) <= center];
+            assert_eq![filter.weight(0.3, -0.2), filter.weight(-0.3, 0.2)];
+            assert_eq![filter.weight(filter.radius() + 0.01, 0.), 0.];
+            assert_eq![filter.weight(0., -filter.radius() - 0.01), 0.];
+        }
+
+        assert_eq![Filter::Box.weight(0.49, -0.49), 1.];
+        assert_eq![Filter::Tent.weight(0.5, 0.), 0.5];
+        assert![Filter::Gaussian.weight(1.5, 0.).abs() < 1e-12];
+        assert![
+            (Filter::Gaussian.weight(0.5, 0.) / Filter::Gaussian.weight(0., 0.) - 0.6).abs() < 0.01
+        ];
+
+        // Known values of the 1D Mitchell-Netravali filter, with its negative lobe
+        assert![(mitchell(0.) - 8. / 9.).abs() < 1e-12];
+        assert![(mitchell(1.) - 1. / 18.).abs() < 1e-12];
+        assert![mitchell(1.5) < 0.];
+        assert![mitchell(2.).abs() < 1e-12];
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!["tent".parse::<Filter>(), Ok(Filter::Tent)];
+        assert_eq!["mitchell".parse::<Filter>(), Ok(Filter::Mitchell)];
+        assert!["lanczos".parse::<Filter>().is_err()];
+    }
+}
@@ -38,7 +38,7 @@ impl FrameBuffer {
     }
 
     pub fn to_vec(&self) -> Vec<u8> {
-        let mut write_buffer = vec![0 as u8; self.width * self.height * 3];
+        let mut write_buffer = vec![0_u8; self.width * self.height * 3];
         let mut i_ = 0;
         for i in 0..self.height {
             for j in 0..self.width {
@@ -51,7 +51,7 @@ impl FrameBuffer {
             }
         }
 
-        return write_buffer;
+        write_buffer
     }
 
     #[allow(dead_code)]
@@ -78,5 +78,5 @@ impl FrameBuffer {
 }
 
 fn quantize(f: f64) -> u8 {
-    (255. * f.max(0.).min(1.)) as u8
+    (255. * f.clamp(0., 1.)) as u8
 }
@@ -68,6 +68,10 @@ impl Vec3f {
         dot(self, self)
     }
 
+    pub fn norm(self) -> f64 {
+        self.squared_norm().sqrt()
+    }
+
     pub fn add(&mut self, other: Vec3f) {
         self.x += other.x;
         self.y += other.y;
@@ -82,6 +86,24 @@ impl Vec3f {
         f64::min(f64::max(self.x, self.y), self.z)
     }
 
+    // Color channels: 0 is x (red), 1 is y (green), 2 is z (blue)
+    pub fn channel(&self, c: usize) -> f64 {
+        match c {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    // Only keep one of the channels, the others are zeroed
+    pub fn masked(&self, c: usize) -> Vec3f {
+        Vec3f {
+            x: if c == 0 { self.x } else { 0. },
+            y: if c == 1 { self.y } else { 0. },
+            z: if c == 2 { self.z } else { 0. },
+        }
+    }
+
     // Common values
     pub fn zero() -> Vec3f {
         Vec3f {
@@ -178,7 +200,7 @@ impl std::fmt::Display for Vec3f {
 }
 
 fn dot(v1: Vec3f, v2: Vec3f) -> f64 {
-    (v1.x * v2.x + v1.y * v2.y + v1.z * v2.z) as f64
+    v1.x * v2.x + v1.y * v2.y + v1.z * v2.z
 }
 
 #[cfg(test)]
@@ -0,0 +1,1571 @@
+use bvh::find_closest_intersect;
+use bvh::intersect_shape_set;
+use bvh::Bvh;
+use geometry::Vec3f;
+use lights::Light;
+use microfacet::Microfacet;
+use optics::dielectric_interface;
+use optics::reflect;
+use optics::Interface;
+use sampling::cosine_hemisphere;
+use sampling::Rng;
+use scene::Scene;
+use shapes::Intersection;
+use spectrum;
+
+// A shading strategy: how much light comes back along a ray.
+// The renderer calls it for every pixel sample, from any thread
+pub trait Integrator: Sync + Send {
+    fn radiance(&self, orig: &Vec3f, dir: Vec3f, scene: &Scene, bvh: &Bvh, rng: &mut Rng) -> Vec3f;
+
+    // Spectral integrators return CIE XYZ instead of RGB,
+    // the renderer converts it when writing the picture
+    fn is_spectral(&self) -> bool {
+        false
+    }
+}
+
+pub const NAMES: [&str; 7] = [
+    "whitted", "path", "spectral", "normals", "depth", "albedo", "ao",
+];
+
+// Integrator from its name, with the default settings
+pub fn create(name: &str) -> Option<Box<dyn Integrator>> {
+    match name {
+        "whitted" => Some(Box::new(Whitted::create_default())),
+        "path" => Some(Box::new(PathTracer::create_default())),
+        "spectral" => Some(Box::new(SpectralPathTracer::create_default())),
+        "normals" => Some(Box::new(Normals {})),
+        "depth" => Some(Box::new(Depth::create_default())),
+        "albedo" => Some(Box::new(Albedo {})),
+        "ao" => Some(Box::new(AmbientOcclusion::create_default())),
+        _ => None,
+    }
+}
+
+fn default_background() -> Vec3f {
+    Vec3f {
+        x: 0.1,
+        y: 0.1,
+        z: 0.1,
+    }
+}
+
+// ************************************************************
+// Direct lighting, shared by the Whitted and path tracing integrators
+// ************************************************************
+
+fn diffusion_factor(intersection: &Intersection, light_dir: &Vec3f) -> f64 {
+    light_dir.dot(intersection.normal).max(0.)
+}
+
+fn specular_factor(intersection: &Intersection, origin: &Vec3f, light_dir: &Vec3f) -> f64 {
+    // Compute the light reflected vector at that point
+    let incident = -*light_dir;
+    let reflected = reflect(incident, intersection.normal);
+
+    // The specular reflection coeff is the dot product in between the purely
+    // reflected ray and the viewerś point of view
+    let dir_to_viewer = (*origin - intersection.point).normalized();
+    reflected.dot(dir_to_viewer).max(0.)
+}
+
+// Go through the lights which are not shadowed, with their direction
+// and their diffuse and specular factors
+fn for_each_visible_light<F: FnMut(&Light, &Vec3f, f64, f64)>(
+    origin: &Vec3f,
+    intersection: &Intersection,
+    scene: &Scene,
+    bvh: &Bvh,
+    mut f: F,
+) {
+    let mut intersect_orig: Vec3f;
+
+    for light in &scene.lights {
+        let to_light = light.position - intersection.point;
+        if to_light.squared_norm() == 0. {
+            // The light sits right on the surface, no defined direction
+            continue;
+        }
+        let light_dir = to_light.normalized();
+
+        intersect_orig = if light_dir.dot(intersection.normal) < 0. {
+            intersection.point - intersection.normal.scaled(1e-3)
+        } else {
+            intersection.point + intersection.normal.scaled(1e-3)
+        };
+
+        if intersect_shape_set(&intersect_orig, &light_dir, &scene.shapes, bvh) {
+            // Cast shadow, this light is not visible from this point of view
+            continue;
+        }
+
+        let diffusion = diffusion_factor(intersection, &light_dir);
+        let specular = (specular_factor(intersection, origin, &light_dir)
+            * intersection.specular())
+        .powf(intersection.reflectance.specular_exponent);
+        f(light, &light_dir, diffusion, specular);
+    }
+}
+
+// Light from a given direction reflected by a physically based material, towards the origin
+fn microfacet_reflection(
+    origin: &Vec3f,
+    intersection: &Intersection,
+    microfacet: &Microfacet,
+    light_dir: &Vec3f,
+) -> Vec3f {
+    microfacet.eval(
+        intersection.diffuse_color(),
+        intersection.reflectance.refractive_index,
+        intersection.normal,
+        (*origin - intersection.point).normalized(),
+        *light_dir,
+    )
+}
+
+fn microfacet_direct_lighting(
+    origin: &Vec3f,
+    intersection: &Intersection,
+    microfacet: &Microfacet,
+    scene: &Scene,
+    bvh: &Bvh,
+) -> Vec3f {
+    let mut light_intensity = Vec3f::zero();
+    for_each_visible_light(
+        origin,
+        intersection,
+        scene,
+        bvh,
+        |light, light_dir, _, _| {
+            light_intensity += (light.color
+                * microfacet_reflection(origin, intersection, microfacet, light_dir))
+            .scaled(light.intensity);
+        },
+    );
+    light_intensity
+}
+
+fn direct_lighting(origin: &Vec3f, intersection: &Intersection, scene: &Scene, bvh: &Bvh) -> Vec3f {
+    // Compute the lighting contribution of direct illumination,
+    // meaning diffuse and specular lighting
+    let mut light_intensity = Vec3f::zero();
+
+    if let Some(ref microfacet) = intersection.reflectance.microfacet {
+        return microfacet_direct_lighting(origin, intersection, microfacet, scene, bvh);
+    }
+
+    for_each_visible_light(
+        origin,
+        intersection,
+        scene,
+        bvh,
+        |light, _, diffusion, specular| {
+            // Handle diffuse lighting
+            light_intensity += (light.color * intersection.diffuse_color())
+                .scaled(diffusion)
+                .scaled(light.intensity);
+
+            // Handle specular reflections, white
+            light_intensity += light.color.scaled(specular);
+        },
+    );
+
+    light_intensity.scaled(intersection.reflectance.diffusion)
+}
+
+// Direct lighting of the path tracers, with the same reflectance as their bounces:
+// Lambertian (albedo / pi), or physically based. Like with the microfacet materials,
+// the intensity of the lights is scaled by pi, a white surface facing a light sends
+// all of it back. No Phong highlight, it would add up with the diffuse reflection
+fn path_direct_lighting(
+    origin: &Vec3f,
+    intersection: &Intersection,
+    scene: &Scene,
+    bvh: &Bvh,
+) -> Vec3f {
+    if let Some(ref microfacet) = intersection.reflectance.microfacet {
+        return microfacet_direct_lighting(origin, intersection, microfacet, scene, bvh);
+    }
+
+    let albedo = intersection
+        .diffuse_color()
+        .scaled(intersection.reflectance.diffusion);
+    let mut light_intensity = Vec3f::zero();
+    for_each_visible_light(
+        origin,
+        intersection,
+        scene,
+        bvh,
+        |light, _, diffusion, _| {
+            light_intensity += (light.color * albedo).scaled(diffusion * light.intensity);
+        },
+    );
+    light_intensity
+}
+
+fn facing_normal(intersection: &Intersection, dir: &Vec3f) -> Vec3f {
+    // Normal on the side the ray is coming from
+    if intersection.normal.dot(*dir) > 0. {
+        -intersection.normal
+    } else {
+        intersection.normal
+    }
+}
+
+// Share of the light a glass like surface reflects, the rest is refracted.
+// Angle dependent (Fresnel) at an interface in between two media, the fixed
+// reflection split otherwise (mirrors, partially dissolved materials)
+fn reflection_ratio(intersection: &Intersection, interface: &Interface) -> f64 {
+    if interface.eta == 1. {
+        return intersection.reflectance.reflection;
+    }
+    interface.reflected_weight
+}
+
+// Distance travelled inside an absorbing object, when its surface is hit from within.
+// None from the outside, or through clear materials
+fn distance_inside(origin: &Vec3f, dir: &Vec3f, intersection: &Intersection) -> Option<f64> {
+    if intersection.normal.dot(*dir) <= 0. || intersection.reflectance.absorption == Vec3f::zero() {
+        return None;
+    }
+    Some((intersection.point - *origin).norm())
+}
+
+// ************************************************************
+// Whitted: direct lighting, plus mirror and glass recursion
+// ************************************************************
+
+pub struct Whitted {
+    pub background: Vec3f, // Ambient light, and what the reflected rays see when escaping
+    pub max_recursion: u8,
+}
+
+impl Whitted {
+    pub fn create_default() -> Whitted {
+        Whitted {
+            background: default_background(),
+            max_recursion: 3,
+        }
+    }
+
+    fn reflected_lighting(
+        &self,
+        incident: Vec3f,
+        intersection: &Intersection,
+        scene: &Scene,
+        bvh: &Bvh,
+        n_recursion: u8,
+        channel: Option<usize>,
+    ) -> Vec3f {
+        let interface = dielectric_interface(
+            incident,
+            intersection,
+            intersection.reflectance.refractive_index_for(channel),
+        );
+        let ratio = reflection_ratio(intersection, &interface);
+        if ratio <= 0. {
+            return Vec3f::zero();
+        }
+
+        let reflection = interface.reflected;
+        self.cast_ray(
+            &reflection.0,
+            reflection.1,
+            scene,
+            bvh,
+            n_recursion + 1,
+            channel,
+        )
+        .scaled(ratio)
+    }
+
+    // Compute the lighting contribution of a refracted ray. Dispersive surfaces
+    // split the white light, every channel is then followed on its own
+    fn refracted_lighting(
+        &self,
+        incident: Vec3f,
+        intersection: &Intersection,
+        scene: &Scene,
+        bvh: &Bvh,
+        n_recursion: u8,
+        channel: Option<usize>,
+    ) -> Vec3f {
+        let channels = if channel.is_none() && intersection.reflectance.dispersion.is_some() {
+            vec![Some(0), Some(1), Some(2)]
+        } else {
+            vec![channel]
+        };
+
+        let mut light_intensity = Vec3f::zero();
+        for c in channels {
+            // We may or may not have a refracted ray, angle dependent
+            let interface = dielectric_interface(
+                incident,
+                intersection,
+                intersection.reflectance.refractive_index_for(c),
+            );
+
+            if let Some(refracted_ray) = interface.transmitted {
+                let light = self
+                    .cast_ray(
+                        &refracted_ray.0,
+                        refracted_ray.1,
+                        scene,
+                        bvh,
+                        n_recursion + 1,
+                        c,
+                    )
+                    .scaled(1. - reflection_ratio(intersection, &interface));
+
+                // Keep the channel which has just been split
+                light_intensity += match (channel, c) {
+                    (None, Some(c)) => light.masked(c),
+                    _ => light,
+                };
+            }
+        }
+        light_intensity
+    }
+
+    // Mirror reflection and refraction of a physically based material. Only the perfectly
+    // smooth directions are followed, the blurry ones need the path tracer
+    #[allow(clippy::too_many_arguments)]
+    fn microfacet_lighting(
+        &self,
+        incident: Vec3f,
+        intersection: &Intersection,
+        microfacet: &Microfacet,
+        scene: &Scene,
+        bvh: &Bvh,
+        n_recursion: u8,
+        channel: Option<usize>,
+    ) -> Vec3f {
+        let refractive_index = intersection.reflectance.refractive_index_for(channel);
+        let (reflected, refracted) = microfacet.mirror_weights(
+            intersection.diffuse_color(),
+            refractive_index,
+            intersection.normal,
+            -incident,
+        );
+        let interface = dielectric_interface(incident, intersection, refractive_index);
+
+        let mut light_intensity = Vec3f::zero();
+        let mut follow = |ray: (Vec3f, Vec3f), weight: Vec3f| {
+            if weight.max() > 0. {
+                light_intensity +=
+                    self.cast_ray(&ray.0, ray.1, scene, bvh, n_recursion + 1, channel) * weight;
+            }
+        };
+        follow(interface.reflected, reflected);
+        if let Some(ray) = interface.transmitted {
+            follow(ray, refracted);
+        }
+        light_intensity
+    }
+
+    fn cast_ray(
+        &self,
+        orig: &Vec3f,
+        dir: Vec3f,
+        scene: &Scene,
+        bvh: &Bvh,
+        n_recursion: u8,
+        channel: Option<usize>, // Color channel followed after a dispersive surface
+    ) -> Vec3f {
+        if n_recursion > self.max_recursion {
+            return self.background;
+        }
+
+        let result = find_closest_intersect(orig, dir, &scene.shapes, bvh);
+
+        match result {
+            Some(intersect_result) => {
+                let intersection = &intersect_result.0;
+
+                let mut light_intensity = self.background + intersection.reflectance.emission;
+
+                // Go through all the lights, sum up the individual contributions
+                light_intensity += direct_lighting(orig, intersection, scene, bvh);
+
+                if let Some(ref microfacet) = intersection.reflectance.microfacet {
+                    light_intensity += self.microfacet_lighting(
+                        dir,
+                        intersection,
+                        microfacet,
+                        scene,
+                        bvh,
+                        n_recursion,
+                        channel,
+                    );
+                } else if intersection.reflectance.is_glass_like {
+                    // Compute the reflections recursively
+                    light_intensity += self.reflected_lighting(
+                        dir,
+                        intersection,
+                        scene,
+                        bvh,
+                        n_recursion,
+                        channel,
+                    );
+
+                    // Compute the refracted light recusively
+                    light_intensity += self.refracted_lighting(
+                        dir,
+                        intersection,
+                        scene,
+                        bvh,
+                        n_recursion,
+                        channel,
+                    );
+                }
+
+                // Whatever comes back from within an object is partly absorbed on the way
+                if let Some(distance) = distance_inside(orig, &dir, intersection) {
+                    light_intensity =
+                        light_intensity * intersection.reflectance.transmittance(distance);
+                }
+                light_intensity
+            }
+            // No intersection, do nothing and test the next shape
+            _ => {
+                if n_recursion > 1 {
+                    self.background
+                } else {
+                    Vec3f::zero()
+                }
+            }
+        }
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        orig: &Vec3f,
+        dir: Vec3f,
+        scene: &Scene,
+        bvh: &Bvh,
+        _rng: &mut Rng,
+    ) -> Vec3f {
+        self.cast_ray(orig, dir, scene, bvh, 1, None)
+    }
+}
+
+// ************************************************************
+// Path tracing
+// ************************************************************
+
+pub struct PathTracer {
+    pub background: Vec3f,      // Uniform environment light
+    pub max_path_length: usize, // Russian roulette usually stops well before
+    pub roulette_depth: usize,  // Path length after which the paths are randomly terminated
+}
+
+impl PathTracer {
+    pub fn create_default() -> PathTracer {
+        PathTracer {
+            background: default_background(),
+            max_path_length: 32,
+            roulette_depth: 3,
+        }
+    }
+}
+
+fn diffuse_bounce(intersection: &Intersection, dir: &Vec3f, rng: &mut Rng) -> (Vec3f, Vec3f) {
+    // Cosine weighted, the cosine and the pdf cancel out with a lambertian surface
+    let normal = facing_normal(intersection, dir);
+    let bounce = cosine_hemisphere(&normal, rng.next_f64(), rng.next_f64());
+    (intersection.point + normal.scaled(1e-3), bounce)
+}
+
+// Chance for a path to follow the mirror reflection or the refraction of a glass like surface
+// instead of the diffuse reflection, in proportion to what they weigh in its reflectance:
+// the diffuse light is scaled by the diffusion, the reflected and refracted shares add up to one
+fn specular_probability(intersection: &Intersection) -> f64 {
+    let reflectance = &intersection.reflectance;
+    if !reflectance.is_glass_like || reflectance.microfacet.is_some() {
+        return 0.;
+    }
+    1. / (1. + reflectance.diffusion.max(0.))
+}
+
+fn specular_bounce(
+    intersection: &Intersection,
+    dir: &Vec3f,
+    channel: Option<usize>,
+    rng: &mut Rng,
+) -> (Vec3f, Vec3f) {
+    // Pick either the mirror reflection or the refraction, in the same proportions
+    // as the Whitted integrator does. Total internal reflection falls back to the mirror
+    let interface = dielectric_interface(
+        *dir,
+        intersection,
+        intersection.reflectance.refractive_index_for(channel),
+    );
+
+    if rng.next_f64() < reflection_ratio(intersection, &interface) {
+        return interface.reflected;
+    }
+
+    interface.transmitted.unwrap_or(interface.reflected)
+}
+
+// Sampled direction off a physically based material, with the weight of the path.
+// None when the light is absorbed
+fn microfacet_bounce(
+    intersection: &Intersection,
+    microfacet: &Microfacet,
+    dir: &Vec3f,
+    refractive_index: f64,
+    rng: &mut Rng,
+) -> Option<((Vec3f, Vec3f), Vec3f)> {
+    let (bounce, weight) = microfacet.sample(
+        intersection.diffuse_color(),
+        refractive_index,
+        intersection.normal,
+        -*dir,
+        rng.next_f64(),
+        rng.next_f64(),
+        rng.next_f64(),
+    )?;
+
+    // Offset on the side the path goes to
+    let normal = if bounce.dot(intersection.normal) > 0. {
+        intersection.normal
+    } else {
+        -intersection.normal
+    };
+    Some(((intersection.point + normal.scaled(1e-3), bounce), weight))
+}
+
+// What a path carries, per color channel or per wavelength, and the light found along
+// the way. The random walk itself is the same whatever is carried
+trait PathCarrier {
+    // Uniform environment light, found when the path escapes
+    fn add_background(&mut self, background: Vec3f);
+
+    // Light emitted by the surface, and its direct lighting
+    fn add_surface_lighting(
+        &mut self,
+        origin: &Vec3f,
+        intersection: &Intersection,
+        scene: &Scene,
+        bvh: &Bvh,
+    );
+
+    // Beer-Lambert, over the distance travelled inside the object
+    fn absorb(&mut self, intersection: &Intersection, distance: f64);
+
+    // Lambertian reflection, the direction is cosine sampled
+    fn diffuse(&mut self, intersection: &Intersection);
+
+    // Mirror reflection or refraction, picked at random
+    fn specular_bounce(
+        &mut self,
+        intersection: &Intersection,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> (Vec3f, Vec3f);
+
+    // Physically based materials, None when the light is absorbed
+    fn microfacet_bounce(
+        &mut self,
+        intersection: &Intersection,
+        microfacet: &Microfacet,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> Option<(Vec3f, Vec3f)>;
+
+    fn max_throughput(&self) -> f64;
+    fn scale(&mut self, s: f64);
+
+    // Estimate of the light coming along the first ray
+    fn radiance(&self) -> Vec3f;
+}
+
+// Follow a single random path, return an unbiased estimate of the light coming along the ray.
+// The point lights are sampled explicitly at every bounce (next event estimation),
+// emissive surfaces and the background are only found by chance
+#[allow(clippy::too_many_arguments)]
+fn random_walk<C: PathCarrier>(
+    mut carrier: C,
+    orig: &Vec3f,
+    dir: Vec3f,
+    scene: &Scene,
+    bvh: &Bvh,
+    rng: &mut Rng,
+    background: Vec3f,
+    max_path_length: usize,
+    roulette_depth: usize,
+) -> Vec3f {
+    let mut origin = *orig;
+    let mut dir = dir;
+
+    for depth in 0..max_path_length {
+        let intersection = match find_closest_intersect(&origin, dir, &scene.shapes, bvh) {
+            Some(intersect_result) => intersect_result.0,
+            None => {
+                // Same as Whitted, the background only lights the scene
+                if depth > 0 {
+                    carrier.add_background(background);
+                }
+                break;
+            }
+        };
+
+        // Absorption along the way, when leaving an object
+        if let Some(distance) = distance_inside(&origin, &dir, &intersection) {
+            carrier.absorb(&intersection, distance);
+        }
+
+        // The direct lighting only concerns the diffuse reflection, the mirror and
+        // the refraction of the glass like surfaces cannot see a point light
+        carrier.add_surface_lighting(&origin, &intersection, scene, bvh);
+        let specular_probability = specular_probability(&intersection);
+
+        let next_ray = if let Some(ref microfacet) = intersection.reflectance.microfacet {
+            match carrier.microfacet_bounce(&intersection, microfacet, &dir, rng) {
+                Some(ray) => ray,
+                None => break,
+            }
+        } else if rng.next_f64() < specular_probability {
+            carrier.scale(1. / specular_probability);
+            carrier.specular_bounce(&intersection, &dir, rng)
+        } else {
+            carrier.scale(1. / (1. - specular_probability));
+            carrier.diffuse(&intersection);
+            diffuse_bounce(&intersection, &dir, rng)
+        };
+
+        // Russian roulette, randomly stop the paths which do not carry much light anymore
+        if depth >= roulette_depth {
+            let survival = carrier.max_throughput().min(0.95);
+            if rng.next_f64() >= survival {
+                break;
+            }
+            carrier.scale(1. / survival);
+        }
+
+        origin = next_ray.0;
+        dir = next_ray.1;
+    }
+
+    carrier.radiance()
+}
+
+// Light per color channel
+struct RgbPath {
+    radiance: Vec3f,
+    throughput: Vec3f,
+    channel: Option<usize>, // Single color channel, past a dispersive surface
+}
+
+impl RgbPath {
+    fn create() -> RgbPath {
+        RgbPath {
+            radiance: Vec3f::zero(),
+            throughput: Vec3f::ones(),
+            channel: None,
+        }
+    }
+
+    // Dispersion, follow one of the channels at random from there on
+    fn pick_channel(&mut self, intersection: &Intersection, rng: &mut Rng) {
+        if self.channel.is_none() && intersection.reflectance.dispersion.is_some() {
+            let c = ((rng.next_f64() * 3.) as usize).min(2);
+            self.throughput = self.throughput.masked(c).scaled(3.);
+            self.channel = Some(c);
+        }
+    }
+}
+
+impl PathCarrier for RgbPath {
+    fn add_background(&mut self, background: Vec3f) {
+        self.radiance += self.throughput * background;
+    }
+
+    fn add_surface_lighting(
+        &mut self,
+        origin: &Vec3f,
+        intersection: &Intersection,
+        scene: &Scene,
+        bvh: &Bvh,
+    ) {
+        self.radiance += self.throughput * intersection.reflectance.emission;
+        self.radiance += self.throughput * path_direct_lighting(origin, intersection, scene, bvh);
+    }
+
+    fn absorb(&mut self, intersection: &Intersection, distance: f64) {
+        self.throughput = self.throughput * intersection.reflectance.transmittance(distance);
+    }
+
+    fn diffuse(&mut self, intersection: &Intersection) {
+        self.throughput = self.throughput
+            * intersection
+                .diffuse_color()
+                .scaled(intersection.reflectance.diffusion);
+    }
+
+    fn specular_bounce(
+        &mut self,
+        intersection: &Intersection,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> (Vec3f, Vec3f) {
+        self.pick_channel(intersection, rng);
+        specular_bounce(intersection, dir, self.channel, rng)
+    }
+
+    fn microfacet_bounce(
+        &mut self,
+        intersection: &Intersection,
+        microfacet: &Microfacet,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> Option<(Vec3f, Vec3f)> {
+        if microfacet.transmission > 0. {
+            self.pick_channel(intersection, rng);
+        }
+        let refractive_index = intersection.reflectance.refractive_index_for(self.channel);
+        let (ray, weight) =
+            microfacet_bounce(intersection, microfacet, dir, refractive_index, rng)?;
+        self.throughput = self.throughput * weight;
+        Some(ray)
+    }
+
+    fn max_throughput(&self) -> f64 {
+        self.throughput.max()
+    }
+
+    fn scale(&mut self, s: f64) {
+        self.throughput = self.throughput.scaled(s);
+    }
+
+    fn radiance(&self) -> Vec3f {
+        self.radiance
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, orig: &Vec3f, dir: Vec3f, scene: &Scene, bvh: &Bvh, rng: &mut Rng) -> Vec3f {
+        random_walk(
+            RgbPath::create(),
+            orig,
+            dir,
+            scene,
+            bvh,
+            rng,
+            self.background,
+            self.max_path_length,
+            self.roulette_depth,
+        )
+    }
+}
+
+// ************************************************************
+// Spectral path tracing, hero wavelength sampling
+// See "Hero wavelength spectral sampling", Wilkie et al., 2014
+// ************************************************************
+
+pub struct SpectralPathTracer {
+    pub background: Vec3f, // Uniform environment light, upsampled
+    pub max_path_length: usize,
+    pub roulette_depth: usize,
+}
+
+impl SpectralPathTracer {
+    pub fn create_default() -> SpectralPathTracer {
+        SpectralPathTracer {
+            background: default_background(),
+            max_path_length: 32,
+            roulette_depth: 3,
+        }
+    }
+}
+
+// Wavelengths carried by every path: a random hero one, and the others evenly spread over
+// the visible range. They all follow the same path until a dispersive surface
+const N_WAVELENGTHS: usize = 4;
+
+struct Wavelengths {
+    values: [f64; N_WAVELENGTHS], // In nanometers, the hero one first
+    throughput: [f64; N_WAVELENGTHS],
+    hero_only: bool,
+}
+
+impl Wavelengths {
+    fn create(u: f64) -> Wavelengths {
+        let range = spectrum::WAVELENGTH_MAX - spectrum::WAVELENGTH_MIN;
+        let mut values = [0.; N_WAVELENGTHS];
+        for (i, value) in values.iter_mut().enumerate() {
+            let offset = u + i as f64 / N_WAVELENGTHS as f64;
+            *value = spectrum::WAVELENGTH_MIN + (offset - offset.floor()) * range;
+        }
+
+        Wavelengths {
+            values,
+            throughput: [1.; N_WAVELENGTHS],
+            hero_only: false,
+        }
+    }
+
+    fn scale<F: Fn(f64) -> f64>(&mut self, f: F) {
+        for (throughput, wavelength) in self.throughput.iter_mut().zip(self.values.iter()) {
+            *throughput *= f(*wavelength);
+        }
+    }
+
+    // The other wavelengths would go their own way, the hero one now stands for all of them
+    fn keep_hero(&mut self) {
+        if !self.hero_only {
+            self.throughput[0] *= N_WAVELENGTHS as f64;
+            for throughput in self.throughput.iter_mut().skip(1) {
+                *throughput = 0.;
+            }
+            self.hero_only = true;
+        }
+    }
+
+    fn max_throughput(&self) -> f64 {
+        self.throughput.iter().cloned().fold(0., f64::max)
+    }
+}
+
+fn spectral_direct_lighting(
+    origin: &Vec3f,
+    intersection: &Intersection,
+    scene: &Scene,
+    bvh: &Bvh,
+    wavelengths: &Wavelengths,
+) -> [f64; N_WAVELENGTHS] {
+    // Same as the RGB direct lighting of the path tracer, wavelength per wavelength
+    let mut light_intensity = [0.; N_WAVELENGTHS];
+
+    for_each_visible_light(
+        origin,
+        intersection,
+        scene,
+        bvh,
+        |light, light_dir, diffusion, _| {
+            // Physically based materials are upsampled after the fact, they are linear in the colors
+            let microfacet = intersection.reflectance.microfacet.map(|microfacet| {
+                microfacet_reflection(origin, intersection, &microfacet, light_dir)
+                    .scaled(light.intensity)
+            });
+
+            for (intensity, wavelength) in light_intensity.iter_mut().zip(wavelengths.values.iter())
+            {
+                if let Some(reflected) = microfacet {
+                    *intensity += light.power_at(*wavelength)
+                        * spectrum::rgb_to_spectral(reflected, *wavelength);
+                    continue;
+                }
+                *intensity += light.power_at(*wavelength)
+                    * intersection.diffuse_at(*wavelength)
+                    * intersection.reflectance.diffusion
+                    * diffusion
+                    * light.intensity;
+            }
+        },
+    );
+    light_intensity
+}
+
+fn spectral_specular_bounce(
+    intersection: &Intersection,
+    dir: &Vec3f,
+    wavelengths: &mut Wavelengths,
+    rng: &mut Rng,
+) -> (Vec3f, Vec3f) {
+    // The hero wavelength picks the reflection or the refraction,
+    // the others are weighted by how likely they were to follow
+    let interface = |wavelength: f64| {
+        dielectric_interface(
+            *dir,
+            intersection,
+            intersection.reflectance.refractive_index_at(wavelength),
+        )
+    };
+    let ratio = |wavelength: f64| reflection_ratio(intersection, &interface(wavelength));
+    let hero = interface(wavelengths.values[0]);
+    let hero_ratio = reflection_ratio(intersection, &hero);
+
+    if rng.next_f64() < hero_ratio {
+        wavelengths.scale(|l| ratio(l) / hero_ratio);
+        return hero.reflected;
+    }
+
+    match hero.transmitted {
+        Some(ray) => {
+            if intersection.reflectance.dispersion.is_some() {
+                wavelengths.keep_hero();
+            } else {
+                wavelengths.scale(|l| (1. - ratio(l)) / (1. - hero_ratio));
+            }
+            ray
+        }
+        None => hero.reflected,
+    }
+}
+
+// Light per wavelength, the hero one and its companions
+struct SpectralPath {
+    wavelengths: Wavelengths,
+    radiance: [f64; N_WAVELENGTHS],
+}
+
+impl PathCarrier for SpectralPath {
+    fn add_background(&mut self, background: Vec3f) {
+        for (i, value) in self.radiance.iter_mut().enumerate() {
+            let wavelength = self.wavelengths.values[i];
+            *value +=
+                self.wavelengths.throughput[i] * spectrum::rgb_to_spectral(background, wavelength);
+        }
+    }
+
+    fn add_surface_lighting(
+        &mut self,
+        origin: &Vec3f,
+        intersection: &Intersection,
+        scene: &Scene,
+        bvh: &Bvh,
+    ) {
+        let wavelengths = &self.wavelengths;
+        let direct = spectral_direct_lighting(origin, intersection, scene, bvh, wavelengths);
+        for (i, value) in self.radiance.iter_mut().enumerate() {
+            let emission = intersection.reflectance.emission_at(wavelengths.values[i]);
+            *value += wavelengths.throughput[i] * (emission + direct[i]);
+        }
+    }
+
+    fn absorb(&mut self, intersection: &Intersection, distance: f64) {
+        let reflectance = &intersection.reflectance;
+        self.wavelengths
+            .scale(|l| reflectance.transmittance_at(l, distance));
+    }
+
+    fn diffuse(&mut self, intersection: &Intersection) {
+        self.wavelengths
+            .scale(|l| intersection.diffuse_at(l) * intersection.reflectance.diffusion);
+    }
+
+    fn specular_bounce(
+        &mut self,
+        intersection: &Intersection,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> (Vec3f, Vec3f) {
+        spectral_specular_bounce(intersection, dir, &mut self.wavelengths, rng)
+    }
+
+    fn microfacet_bounce(
+        &mut self,
+        intersection: &Intersection,
+        microfacet: &Microfacet,
+        dir: &Vec3f,
+        rng: &mut Rng,
+    ) -> Option<(Vec3f, Vec3f)> {
+        // Sampled for the hero wavelength, the weights are upsampled like the colors
+        let reflectance = &intersection.reflectance;
+        let refractive_index = reflectance.refractive_index_at(self.wavelengths.values[0]);
+        let (ray, weight) =
+            microfacet_bounce(intersection, microfacet, dir, refractive_index, rng)?;
+
+        let through = ray.1.dot(intersection.normal) * dir.dot(intersection.normal) > 0.;
+        if through && reflectance.dispersion.is_some() {
+            self.wavelengths.keep_hero();
+        }
+        self.wavelengths
+            .scale(|l| spectrum::rgb_to_spectral(weight, l));
+        Some(ray)
+    }
+
+    fn max_throughput(&self) -> f64 {
+        self.wavelengths.max_throughput()
+    }
+
+    fn scale(&mut self, s: f64) {
+        self.wavelengths.scale(|_| s);
+    }
+
+    fn radiance(&self) -> Vec3f {
+        // Monte Carlo estimate of the XYZ integrals, the wavelengths are uniformly distributed
+        let mut xyz = Vec3f::zero();
+        for (value, wavelength) in self.radiance.iter().zip(self.wavelengths.values.iter()) {
+            xyz += spectrum::cie_xyz(*wavelength).scaled(*value);
+        }
+        let range = spectrum::WAVELENGTH_MAX - spectrum::WAVELENGTH_MIN;
+        xyz.scaled(range / (N_WAVELENGTHS as f64 * spectrum::luminance_integral()))
+    }
+}
+
+impl Integrator for SpectralPathTracer {
+    fn radiance(&self, orig: &Vec3f, dir: Vec3f, scene: &Scene, bvh: &Bvh, rng: &mut Rng) -> Vec3f {
+        // Same random walk as the RGB path tracer, carrying a few wavelengths at once
+        let path = SpectralPath {
+            wavelengths: Wavelengths::create(rng.next_f64()),
+            radiance: [0.; N_WAVELENGTHS],
+        };
+        random_walk(
+            path,
+            orig,
+            dir,
+            scene,
+            bvh,
+            rng,
+            self.background,
+            self.max_path_length,
+            self.roulette_depth,
+        )
+    }
+
+    fn is_spectral(&self) -> bool {
+        true
+    }
+}
+
+// ************************************************************
+// Debug integrators, first hit only. Black when nothing is hit
+// ************************************************************
+
+fn first_hit(orig: &Vec3f, dir: Vec3f, scene: &Scene, bvh: &Bvh) -> Option<Intersection> {
+    find_closest_intersect(orig, dir, &scene.shapes, bvh).map(|hit| hit.0)
+}
+
+// Shading normal, from [-1, 1] to [0, 1] per channel
+pub struct Normals {}
+
+impl Integrator for Normals {
+    fn radiance(
+        &self,
+        orig: &Vec3f,
+        dir: Vec3f,
+        scene: &Scene,
+        bvh: &Bvh,
+        _rng: &mut Rng,
+    ) -> Vec3f {
+        match first_hit(orig, dir, scene, bvh) {
+            Some(intersection) => (intersection.normal + Vec3f::ones()).scaled(0.5),
+            None => Vec3f::zero(),
+        }
+    }
+}
+
+// Distance to the camera, white up close and black from max_distance on
+pub struct Depth {
+    pub max_distance: f64,
+}
+
+impl Depth {
+    pub fn create_default() -> Depth {
+        Depth { max_distance: 100. }
+    }
+}
+
+impl Integrator for Depth {
+    fn radiance(
+        &self,
+        orig: &Vec3f,
+        dir: Vec3f,
+        scene: &Scene,
+        bvh: &Bvh,
+        _rng: &mut Rng,
+    ) -> Vec3f {
+        match first_hit(orig, dir, scene, bvh) {
+            Some(intersection) => {
+                let distance = (intersection.point - *orig).norm();
+                Vec3f::ones().scaled((1. - distance / self.max_distance).max(0.))
+            }
+            None => Vec3f::zero(),
+        }
+    }
+}
+
+// Diffuse color, textures included, without any lighting
+pub struct Albedo {}
+
+impl Integrator for Albedo {
+    fn radiance(
+        &self,
+        orig: &Vec3f,
+        dir: Vec3f,
+        scene: &Scene,
+        bvh: &Bvh,
+        _rng: &mut Rng,
+    ) -> Vec3f {
+        match first_hit(orig, dir, scene, bvh) {
+            Some(intersection) => intersection.diffuse_color(),
+            None => Vec3f::zero(),
+        }
+    }
+}
+
+// Fraction of the hemisphere which is not occluded within a given distance,
+// cosine weighted
+pub struct AmbientOcclusion {
+    pub samples: usize,
+    pub distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn create_default() -> AmbientOcclusion {
+        AmbientOcclusion {
+            samples: 16,
+            distance: 10.,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, orig: &Vec3f, dir: Vec3f, scene: &Scene, bvh: &Bvh, rng: &mut Rng) -> Vec3f {
+        let intersection = match first_hit(orig, dir, scene, bvh) {
+            Some(intersection) => intersection,
+            None => return Vec3f::zero(),
+        };
+
+        let mut visible = 0;
+        for _ in 0..self.samples {
+            let (occlusion_orig, occlusion_dir) = diffuse_bounce(&intersection, &dir, rng);
+            let occluded =
+                match find_closest_intersect(&occlusion_orig, occlusion_dir, &scene.shapes, bvh) {
+                    Some(hit) => {
+                        (hit.0.point - occlusion_orig).squared_norm()
+                            < self.distance * self.distance
+                    }
+                    None => false,
+                };
+            if !occluded {
+                visible += 1;
+            }
+        }
+
+        Vec3f::ones().scaled(visible as f64 / self.samples.max(1) as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lights::create_light;
+    use optics::Dispersion;
+    use polygon::ConvexPolygon;
+    use shapes::Reflectance;
+    use sphere;
+
+    fn point(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    // White floor, with a red wall on the left and a light above
+    fn red_wall_scene() -> Scene {
+        let mut scene = Scene::new();
+
+        let white = Reflectance::create_default();
+        let mut red = Reflectance::create_default();
+        red.diffuse_color = point(1., 0., 0.);
+
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                point(-2., -1., 0.),
+                point(10., -1., 0.),
+                point(10., -1., -20.),
+                point(-2., -1., -20.),
+            ],
+            white,
+        )));
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                point(-2., -1., 0.),
+                point(-2., -1., -20.),
+                point(-2., 10., -20.),
+                point(-2., 10., 0.),
+            ],
+            red,
+        )));
+        scene
+            .lights
+            .push(create_light(point(0., 5., -5.), Vec3f::ones(), 1.));
+        scene
+    }
+
+    fn average(
+        integrator: &dyn Integrator,
+        scene: &Scene,
+        bvh: &Bvh,
+        dir: Vec3f,
+        n: usize,
+    ) -> Vec3f {
+        let mut rng = Rng::create(0);
+        let mut color = Vec3f::zero();
+        for _ in 0..n {
+            color += integrator.radiance(&Vec3f::zero(), dir, scene, bvh, &mut rng);
+        }
+        color.scaled(1. / n as f64)
+    }
+
+    #[test]
+    fn test_create() {
+        for name in NAMES.iter() {
+            assert![create(name).is_some()];
+        }
+        assert![create("photons").is_none()];
+    }
+
+    #[test]
+    fn test_path_direct_lighting() {
+        // White floor, seen where it mirrors the light
+        let mut scene = red_wall_scene();
+        scene.shapes.truncate(1);
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let dir = point(0., -7., -5.).normalized();
+        let (intersection, _) =
+            find_closest_intersect(&Vec3f::zero(), dir, &scene.shapes, &bvh).unwrap();
+        let cos = (point(0., 5., -5.) - intersection.point)
+            .normalized()
+            .dot(intersection.normal);
+
+        // Whitted adds a Phong highlight on top of the diffuse reflection
+        let whitted = direct_lighting(&Vec3f::zero(), &intersection, &scene, &bvh);
+        assert![whitted.x > cos + 0.5];
+
+        // The path tracers only see the Lambertian reflection they also sample
+        let path = path_direct_lighting(&Vec3f::zero(), &intersection, &scene, &bvh);
+        assert![(path - Vec3f::ones().scaled(cos)).norm() < 1e-9];
+        let wavelengths = Wavelengths::create(0.3);
+        let spectral =
+            spectral_direct_lighting(&Vec3f::zero(), &intersection, &scene, &bvh, &wavelengths);
+        for (value, wavelength) in spectral.iter().zip(wavelengths.values.iter()) {
+            let light = &scene.lights[0];
+            assert![(value - light.power_at(*wavelength) * light.intensity * cos).abs() < 1e-9];
+        }
+    }
+
+    #[test]
+    fn test_glass_lobes() {
+        // Half diffuse, half transparent window, lit from above, the sky behind
+        let mut window = Reflectance::create_default();
+        window.is_glass_like = true;
+        window.reflection = 0.;
+        window.diffusion = 0.5;
+
+        let mut scene = Scene::new();
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                point(-10., -1., 0.),
+                point(10., -1., 0.),
+                point(10., -1., -20.),
+                point(-10., -1., -20.),
+            ],
+            window,
+        )));
+        scene
+            .lights
+            .push(create_light(point(0., 5., -5.), Vec3f::ones(), 1.));
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+
+        let background = Vec3f::ones().scaled(0.1);
+        let path_tracer = PathTracer {
+            background,
+            ..PathTracer::create_default()
+        };
+
+        // Diffuse reflection of the light and of the sky, and all of the sky through it
+        let cos = 6. / 37f64.sqrt();
+        let expected = 0.5 * cos + 0.5 * 0.1 + 0.1;
+        let color = average(
+            &path_tracer,
+            &scene,
+            &bvh,
+            point(0., -1., -4.).normalized(),
+            20000,
+        );
+        assert![(color.x - expected).abs() < 0.02 * expected];
+    }
+
+    #[test]
+    fn test_color_bleeding() {
+        let scene = red_wall_scene();
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let dir = point(-1.5, -1., -5.).normalized();
+
+        // Whitted: white floor, neutral color
+        let whitted = Whitted {
+            background: Vec3f::zero(),
+            ..Whitted::create_default()
+        };
+        let direct = average(&whitted, &scene, &bvh, dir, 1);
+        assert![direct.x > 0.];
+        assert![(direct.x - direct.y).abs() < 1e-9];
+
+        // Path tracing: the red wall lights the floor next to it
+        let path_tracer = PathTracer {
+            background: Vec3f::zero(),
+            ..PathTracer::create_default()
+        };
+        let color = average(&path_tracer, &scene, &bvh, dir, 2000);
+        assert![color.y >= direct.y - 1e-9];
+        assert![color.x > 1.1 * color.y];
+    }
+
+    #[test]
+    fn test_debug_integrators() {
+        let scene = red_wall_scene();
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let floor = point(1., -1., -5.).normalized();
+        let wall = point(-1., 0., -2.).normalized();
+        let sky = point(0., 1., 0.);
+
+        // Normals
+        let normals = Normals {};
+        assert![(average(&normals, &scene, &bvh, floor, 1) - point(0.5, 1., 0.5)).norm() < 1e-9];
+        assert![(average(&normals, &scene, &bvh, wall, 1) - point(1., 0.5, 0.5)).norm() < 1e-9];
+        assert_eq![average(&normals, &scene, &bvh, sky, 1), Vec3f::zero()];
+
+        // Albedo
+        let albedo = Albedo {};
+        assert_eq![average(&albedo, &scene, &bvh, floor, 1), Vec3f::ones()];
+        assert_eq![average(&albedo, &scene, &bvh, wall, 1), point(1., 0., 0.)];
+
+        // Depth, closer is brighter
+        let depth = Depth::create_default();
+        let near = average(&depth, &scene, &bvh, point(0., -1., -1.).normalized(), 1);
+        let far = average(&depth, &scene, &bvh, point(0., -1., -10.).normalized(), 1);
+        assert![near.x > far.x && far.x > 0.];
+
+        // Ambient occlusion, open floor versus the foot of the wall
+        let ao = AmbientOcclusion::create_default();
+        let open = average(&ao, &scene, &bvh, point(8., -1., -5.).normalized(), 50);
+        let corner = average(&ao, &scene, &bvh, point(-1.9, -1., -5.).normalized(), 50);
+        assert![open.x > 0.9];
+        assert![corner.x < 0.8];
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // Glass sphere in front of a sharp black and white edge, off its axis
+        let mut scene = Scene::new();
+        let mut white = Reflectance::create_default();
+        white.diffusion = 0.;
+        white.emission = Vec3f::ones();
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                point(1., -20., -10.),
+                point(20., -20., -10.),
+                point(20., 20., -10.),
+                point(1., 20., -10.),
+            ],
+            white,
+        )));
+
+        let mut glass = Reflectance::create_default();
+        glass.diffusion = 0.;
+        glass.is_glass_like = true;
+        glass.refractive_index = 1.5;
+        scene.shapes.push(Box::new(sphere::create(
+            point(0., 0., -5.),
+            1.,
+            glass.clone(),
+        )));
+
+        let whitted = Whitted {
+            background: Vec3f::zero(),
+            max_recursion: 5,
+        };
+        let directions: Vec<Vec3f> = (0..100)
+            .map(|k| point(-0.2 + 0.004 * k as f64, 0., -1.).normalized())
+            .collect();
+        let fringe = |scene: &Scene| {
+            let bvh = Bvh::create_from_shapes(&scene.shapes);
+            directions
+                .iter()
+                .map(|dir| average(&whitted, scene, &bvh, *dir, 1))
+                .map(|color| (color.x - color.z).abs())
+                .fold(0., f64::max)
+        };
+
+        // A single index, shades of grey
+        assert![fringe(&scene) < 1e-9];
+
+        // Blue bends more than red, colored fringes along the edge
+        glass.dispersion = Some(Dispersion::Rgb(point(1.45, 1.5, 1.55)));
+        scene.shapes[1] = Box::new(sphere::create(point(0., 0., -5.), 1., glass));
+        assert![fringe(&scene) > 0.5];
+
+        // The path tracer splits the light too, one channel per path
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let path_tracer = PathTracer {
+            background: Vec3f::zero(),
+            ..PathTracer::create_default()
+        };
+        let mut split = (0., directions[0]);
+        for dir in directions.iter() {
+            let color = average(&whitted, &scene, &bvh, *dir, 1);
+            if (color.x - color.z).abs() > f64::abs(split.0) {
+                split = (color.x - color.z, *dir);
+            }
+        }
+        let color = average(&path_tracer, &scene, &bvh, split.1, 2000);
+        assert![(color.x - color.z) * split.0 > 0.];
+
+        // And the spectral one, one wavelength per path
+        let spectral = SpectralPathTracer {
+            background: Vec3f::zero(),
+            ..SpectralPathTracer::create_default()
+        };
+        let color = spectrum::xyz_to_rgb(average(&spectral, &scene, &bvh, split.1, 2000));
+        assert![(color.x - color.z) * split.0 > 0.];
+    }
+
+    #[test]
+    fn test_microfacet() {
+        let mut scene = red_wall_scene();
+        let dir = point(-1.5, -1., -5.).normalized();
+        let whitted = Whitted {
+            background: Vec3f::zero(),
+            ..Whitted::create_default()
+        };
+        let floor = |reflectance: Reflectance| {
+            Box::new(ConvexPolygon::create(
+                vec![
+                    point(-2., -1., 0.),
+                    point(10., -1., 0.),
+                    point(10., -1., -20.),
+                    point(-2., -1., -20.),
+                ],
+                reflectance,
+            ))
+        };
+
+        // Fully rough without any reflection, same as the Lambertian floor
+        let mut lambert = Reflectance::create_default();
+        lambert.specular = 0.;
+        scene.shapes[0] = floor(lambert.clone());
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let reference = average(&whitted, &scene, &bvh, dir, 1);
+
+        let mut rough = lambert;
+        rough.microfacet = Some(Microfacet::create(0., 1.));
+        scene.shapes[0] = floor(rough);
+        let color = average(&whitted, &scene, &bvh, dir, 1);
+        assert![reference.x > 0. && (color - reference).norm() < 1e-9];
+
+        // A polished metal floor mirrors the red wall
+        let mut metal = Reflectance::create_default();
+        metal.microfacet = Some(Microfacet::create(1., 0.));
+        scene.shapes[0] = floor(metal.clone());
+        let color = average(&whitted, &scene, &bvh, dir, 1);
+        assert![color.x > 0.1 && color.y < 0.01 * color.x];
+
+        // Blurry reflection with the path tracers
+        metal.microfacet = Some(Microfacet::create(1., 0.3));
+        scene.shapes[0] = floor(metal);
+        let path_tracer = PathTracer {
+            background: Vec3f::zero(),
+            ..PathTracer::create_default()
+        };
+        let color = average(&path_tracer, &scene, &bvh, dir, 1000);
+        assert![color.x > 0.05 && color.x > 2. * color.y];
+
+        let spectral = SpectralPathTracer {
+            background: Vec3f::zero(),
+            ..SpectralPathTracer::create_default()
+        };
+        let color = spectrum::xyz_to_rgb(average(&spectral, &scene, &bvh, dir, 1000));
+        assert![color.x > 0.05 && color.x > 2. * color.y];
+    }
+
+    #[test]
+    fn test_absorption() {
+        // Tinted glass sphere in front of a white wall, looking through its center
+        let glass_scene = |radius: f64| {
+            let mut scene = Scene::new();
+            let mut white = Reflectance::create_default();
+            white.diffusion = 0.;
+            white.emission = Vec3f::ones();
+            scene.shapes.push(Box::new(ConvexPolygon::create(
+                vec![
+                    point(-20., -20., -10.),
+                    point(20., -20., -10.),
+                    point(20., 20., -10.),
+                    point(-20., 20., -10.),
+                ],
+                white,
+            )));
+
+            let mut glass = Reflectance::create_default();
+            glass.diffusion = 0.;
+            glass.is_glass_like = true;
+            glass.refractive_index = 1.5;
+            glass.absorption = point(1., 0., 0.);
+            scene
+                .shapes
+                .push(Box::new(sphere::create(point(0., 0., -5.), radius, glass)));
+            scene
+        };
+
+        let whitted = Whitted {
+            background: Vec3f::zero(),
+            max_recursion: 5,
+        };
+        let dir = point(0., 0., -1.);
+
+        // Red is absorbed along the diameter, the other channels go through
+        for radius in [0.5, 1.].iter() {
+            let scene = glass_scene(*radius);
+            let bvh = Bvh::create_from_shapes(&scene.shapes);
+            let color = average(&whitted, &scene, &bvh, dir, 1);
+            assert![color.y > 0.5 && color.y == color.z];
+            assert![(color.x / color.y - (-2. * radius).exp()).abs() < 0.01];
+        }
+
+        // Same with the path tracers
+        let scene = glass_scene(1.);
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let path_tracer = PathTracer {
+            background: Vec3f::zero(),
+            ..PathTracer::create_default()
+        };
+        let color = average(&path_tracer, &scene, &bvh, dir, 2000);
+        assert![(color.x / color.y - (-2f64).exp()).abs() < 0.03];
+
+        let spectral = SpectralPathTracer {
+            background: Vec3f::zero(),
+            ..SpectralPathTracer::create_default()
+        };
+        let color = spectrum::xyz_to_rgb(average(&spectral, &scene, &bvh, dir, 2000));
+        assert![color.x < 0.5 * color.y && color.x < 0.5 * color.z];
+    }
+
+    #[test]
+    fn test_spectral() {
+        let scene = red_wall_scene();
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let spectral = SpectralPathTracer {
+            background: Vec3f::zero(),
+            ..SpectralPathTracer::create_default()
+        };
+        let path_tracer = PathTracer {
+            background: Vec3f::zero(),
+            ..PathTracer::create_default()
+        };
+        assert![spectral.is_spectral() && !path_tracer.is_spectral()];
+
+        // White floor and white light, same picture as the RGB path tracer once back to RGB
+        let floor = point(3., -1., -5.).normalized();
+        let rgb = average(&path_tracer, &scene, &bvh, floor, 4000);
+        let converted = spectrum::xyz_to_rgb(average(&spectral, &scene, &bvh, floor, 4000));
+        assert![(converted - rgb).norm() < 0.1 * rgb.norm()];
+
+        // And the red wall is still red
+        let wall = point(-1., 0., -2.).normalized();
+        let red = spectrum::xyz_to_rgb(average(&spectral, &scene, &bvh, wall, 500));
+        assert![red.x > 2. * red.y && red.x > 2. * red.z];
+
+        // Hero wavelengths, evenly spread over the visible range
+        let wavelengths = Wavelengths::create(0.9);
+        assert![(wavelengths.values[0] - 740.).abs() < 1e-9];
+        assert![(wavelengths.values[1] - 440.).abs() < 1e-9];
+        assert![(wavelengths.values[3] - 640.).abs() < 1e-9];
+    }
+}
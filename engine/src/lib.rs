@@ -0,0 +1,30 @@
+// Core of the ray tracer, free of any UI dependency.
+// The GTK viewer lives in src/bin/viewer.rs, behind the "gui" feature
+
+pub mod bvh;
+pub mod camera;
+pub mod filter;
+pub mod framebuffer;
+pub mod geometry;
+pub mod integrator;
+pub mod lights;
+pub mod microfacet;
+pub mod obj;
+pub mod optics;
+pub mod polygon;
+pub mod procedural;
+pub mod renderer;
+pub mod rig;
+pub mod sampling;
+pub mod scene;
+pub mod sdf;
+pub mod shapes;
+pub mod spectrum;
+pub mod sphere;
+pub mod texture;
+pub mod triangle;
+
+pub use framebuffer::FrameBuffer;
+pub use renderer::Renderer;
+pub use scene::Scene;
+pub use shapes::Shape;
@@ -1,10 +1,14 @@
 use geometry::Vec3f;
+use spectrum;
+use spectrum::Spectrum;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Light {
     pub position: Vec3f,
     pub color: Vec3f, // RGB
     pub intensity: f64,
+    pub spectrum: Option<Arc<Spectrum>>, // Spectral rendering, the color is upsampled if none
 }
 
 pub fn create_light(position: Vec3f, color: Vec3f, intensity: f64) -> Light {
@@ -12,5 +16,16 @@ pub fn create_light(position: Vec3f, color: Vec3f, intensity: f64) -> Light {
         position,
         color: color.normalized_l0(),
         intensity,
+        spectrum: None,
+    }
+}
+
+impl Light {
+    // Relative power at a given wavelength, in nanometers
+    pub fn power_at(&self, wavelength: f64) -> f64 {
+        match self.spectrum {
+            Some(ref spectrum) => spectrum.eval(wavelength),
+            None => spectrum::rgb_to_spectral(self.color, wavelength),
+        }
     }
 }
@@ -0,0 +1,437 @@
+use geometry::Vec3f;
+use optics::fresnel;
+use optics::reflect;
+use sampling::cosine_hemisphere;
+use sampling::orthonormal_basis;
+use std::f64::consts::PI;
+
+// Physically based material, metallic / roughness workflow. GGX distribution of the
+// microfacet normals, Smith masking and shadowing. Schlick Fresnel for the metals, whose
+// reflectance is their color, the exact equations for the dielectrics.
+// The base color is the diffuse color of the reflectance (textures included), the
+// dielectric reflection follows its refractive index (1.5 is typical, 1 means none).
+// A mix of three lobes: a rough metal, rough glass and a glossy coat over a diffuse base
+// See "Microfacet models for refraction through rough surfaces", Walter et al., 2007
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Microfacet {
+    pub metallic: f64,     // 0 for dielectrics, 1 for metals, tinted by the base color
+    pub roughness: f64,    // Perceptual, 0 is a perfect mirror
+    pub transmission: f64, // Share of the dielectric which is refracted instead of diffused
+}
+
+// GGX (Trowbridge-Reitz) density of the microfacet normals, projected on the surface
+pub fn ggx(cos_normal: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let d = cos_normal * cos_normal * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+// Share of the microfacets seen from a direction, not hidden by the others
+pub fn smith_g1(cos_normal: f64, alpha: f64) -> f64 {
+    let c = cos_normal.abs();
+    let a2 = alpha * alpha;
+    2. * c / (c + (a2 + (1. - a2) * c * c).sqrt())
+}
+
+// Microfacet normal around the surface normal, with a density D(h) * cos(h, n).
+// u1 and u2 are uniform in [0, 1[
+pub fn ggx_half_vector(normal: &Vec3f, alpha: f64, u1: f64, u2: f64) -> Vec3f {
+    let tan2 = alpha * alpha * u1 / (1. - u1);
+    let cos = 1. / (1. + tan2).sqrt();
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(normal);
+
+    (tangent.scaled(sin * phi.cos()) + bitangent.scaled(sin * phi.sin()) + normal.scaled(cos))
+        .normalized()
+}
+
+// Schlick's Fresnel with a colored reflectance at normal incidence, for the metals
+fn schlick_color(f0: Vec3f, cos: f64) -> Vec3f {
+    let k = (1. - cos.clamp(0., 1.)).powi(5);
+    f0.scaled(1. - k) + Vec3f::ones().scaled(k)
+}
+
+impl Microfacet {
+    pub fn create(metallic: f64, roughness: f64) -> Microfacet {
+        Microfacet {
+            metallic,
+            roughness,
+            transmission: 0.,
+        }
+    }
+
+    // Width of the GGX distribution, squared perceptual roughness.
+    // Never quite zero, the distribution would be a Dirac
+    pub fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(1e-4)
+    }
+
+    // Weights of the three lobes, metal, glass and coated diffuse
+    fn lobes(&self) -> (f64, f64, f64) {
+        let metallic = self.metallic.clamp(0., 1.);
+        let transmission = self.transmission.clamp(0., 1.);
+        (
+            metallic,
+            (1. - metallic) * transmission,
+            (1. - metallic) * (1. - transmission),
+        )
+    }
+
+    // Light reflected towards the viewer for a unit light from a given direction, cosine included.
+    // Scaled by pi like the Lambertian lighting, a white diffuse surface facing the light sends
+    // all of it back. What the glass refracts goes through, and is not seen from this side
+    pub fn eval(
+        &self,
+        base_color: Vec3f,
+        refractive_index: f64,
+        normal: Vec3f, // Towards the outside
+        to_viewer: Vec3f,
+        to_light: Vec3f,
+    ) -> Vec3f {
+        let entering = normal.dot(to_viewer) >= 0.;
+        let normal = if entering { normal } else { -normal };
+        let (nv, nl) = (normal.dot(to_viewer), normal.dot(to_light));
+        if nv <= 0. || nl <= 0. {
+            return Vec3f::zero();
+        }
+
+        let half = (to_viewer + to_light).normalized();
+        let vh = to_viewer.dot(half);
+        let alpha = self.alpha();
+        let specular = ggx(normal.dot(half), alpha) * smith_g1(nv, alpha) * smith_g1(nl, alpha)
+            / (4. * nv * nl);
+
+        let (metal, glass, coated) = self.lobes();
+        let eta = if entering {
+            1. / refractive_index
+        } else {
+            refractive_index
+        };
+
+        // The diffuse base only gets what the coat does not reflect
+        let brdf = schlick_color(base_color, vh).scaled(metal * specular)
+            + Vec3f::ones().scaled(glass * fresnel(vh, eta, 1.) * specular)
+            + Vec3f::ones().scaled(coated * fresnel(vh, 1., refractive_index) * specular)
+            + base_color.scaled(coated * (1. - fresnel(nv, 1., refractive_index)) / PI);
+        brdf.scaled(PI * nl)
+    }
+
+    // Next direction of a path, and its weight: the light carried over the probability
+    // of the direction, cosine included. u picks the lobe, (u1, u2) the direction.
+    // None when the sampled direction ends up on the wrong side of the surface
+    #[allow(clippy::too_many_arguments)]
+    pub fn sample(
+        &self,
+        base_color: Vec3f,
+        refractive_index: f64,
+        normal: Vec3f, // Towards the outside
+        to_viewer: Vec3f,
+        u: f64,
+        u1: f64,
+        u2: f64,
+    ) -> Option<(Vec3f, Vec3f)> {
+        let entering = normal.dot(to_viewer) >= 0.;
+        let normal = if entering { normal } else { -normal };
+        let nv = normal.dot(to_viewer);
+        let alpha = self.alpha();
+        let half = ggx_half_vector(&normal, alpha, u1, u2);
+        let vh = to_viewer.dot(half);
+        if nv <= 0. || vh <= 0. {
+            return None;
+        }
+
+        // Reflected or refracted along the sampled microfacet: the distribution and the
+        // jacobian of the mapping cancel out, the masking and shadowing remains
+        let weight = |dir: Vec3f, reflected: bool| {
+            let nl = normal.dot(dir);
+            if (nl > 0.) != reflected || nl == 0. {
+                return None;
+            }
+            Some(smith_g1(nv, alpha) * smith_g1(nl, alpha) * vh / (nv * normal.dot(half)))
+        };
+        let reflected = reflect(-to_viewer, half);
+
+        let (metal, glass, _) = self.lobes();
+        if u < metal {
+            let w = weight(reflected, true)?;
+            return Some((reflected, schlick_color(base_color, vh).scaled(w)));
+        }
+
+        if u < metal + glass {
+            // Reflected or refracted by the microfacet, in proportion of its Fresnel term
+            let u = (u - metal) / glass;
+            let eta = if entering {
+                1. / refractive_index
+            } else {
+                refractive_index
+            };
+            if u < fresnel(vh, eta, 1.) {
+                let w = weight(reflected, true)?;
+                return Some((reflected, Vec3f::ones().scaled(w)));
+            }
+
+            let cos_t2 = 1. - eta * eta * (1. - vh * vh);
+            let refracted = (half.scaled(eta * vh - cos_t2.max(0.).sqrt()) - to_viewer.scaled(eta))
+                .normalized();
+            let w = weight(refracted, false)?;
+            return Some((refracted, base_color.scaled(w)));
+        }
+
+        // Coat or diffuse base, in proportion of the coat Fresnel term
+        let u = (u - metal - glass) / (1. - metal - glass);
+        let coat = fresnel(nv, 1., refractive_index);
+        if u < coat {
+            let w = weight(reflected, true)?;
+            return Some((
+                reflected,
+                Vec3f::ones().scaled(fresnel(vh, 1., refractive_index) / coat * w),
+            ));
+        }
+        Some((cosine_hemisphere(&normal, u1, u2), base_color))
+    }
+
+    // For the integrators which only follow the mirror and the refracted directions:
+    // their weights, as if the surface was smooth
+    pub fn mirror_weights(
+        &self,
+        base_color: Vec3f,
+        refractive_index: f64,
+        normal: Vec3f,
+        to_viewer: Vec3f,
+    ) -> (Vec3f, Vec3f) {
+        let cos = normal.dot(to_viewer);
+        let eta = if cos >= 0. {
+            1. / refractive_index
+        } else {
+            refractive_index
+        };
+        let cos = cos.abs();
+        let glass_reflection = fresnel(cos, eta, 1.);
+
+        let (metal, glass, coated) = self.lobes();
+        let reflected = schlick_color(base_color, cos).scaled(metal)
+            + Vec3f::ones()
+                .scaled(glass * glass_reflection + coated * fresnel(cos, 1., refractive_index));
+        let refracted = base_color.scaled(glass * (1. - glass_reflection));
+        (reflected, refracted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use optics::dielectric_interface;
+    use sampling::Rng;
+    use shapes::Intersection;
+    use shapes::Reflectance;
+
+    fn vec(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    fn up() -> Vec3f {
+        vec(0., 1., 0.)
+    }
+
+    // Average weight of the sampled paths, the share of the light which is not absorbed
+    fn albedo(microfacet: &Microfacet, base_color: Vec3f, to_viewer: Vec3f) -> Vec3f {
+        let mut rng = Rng::create(7);
+        let n = 100000;
+        let mut sum = Vec3f::zero();
+        for _ in 0..n {
+            let sample = microfacet.sample(
+                base_color,
+                1.5,
+                up(),
+                to_viewer,
+                rng.next_f64(),
+                rng.next_f64(),
+                rng.next_f64(),
+            );
+            if let Some((_, weight)) = sample {
+                sum += weight;
+            }
+        }
+        sum.scaled(1. / n as f64)
+    }
+
+    // Same, integrating the reflected light over the hemisphere
+    fn integrated_albedo(microfacet: &Microfacet, base_color: Vec3f, to_viewer: Vec3f) -> Vec3f {
+        let mut rng = Rng::create(11);
+        let n = 100000;
+        let mut sum = Vec3f::zero();
+        for _ in 0..n {
+            let to_light = cosine_hemisphere(&up(), rng.next_f64(), rng.next_f64());
+            let light = microfacet.eval(base_color, 1.5, up(), to_viewer, to_light);
+            sum += light.scaled(1. / to_light.y);
+        }
+        sum.scaled(1. / n as f64)
+    }
+
+    #[test]
+    fn test_distribution() {
+        // The projected microfacets cover the surface exactly once
+        for alpha in [0.05, 0.3, 1.].iter() {
+            let steps = 100000;
+            let mut integral = 0.;
+            for k in 0..steps {
+                let theta = (k as f64 + 0.5) / steps as f64 * PI / 2.;
+                integral += ggx(theta.cos(), *alpha) * theta.cos() * theta.sin();
+            }
+            integral *= 2. * PI * PI / 2. / steps as f64;
+            assert![(integral - 1.).abs() < 1e-3];
+        }
+
+        // Nothing hidden head on, more at grazing angles and on rough surfaces
+        assert![(smith_g1(1., 0.5) - 1.).abs() < 1e-12];
+        assert![smith_g1(0.2, 0.5) < smith_g1(0.5, 0.5)];
+        assert![smith_g1(0.2, 0.8) < smith_g1(0.2, 0.5)];
+
+        // The sampled normals stay around the surface normal, tighter when smooth
+        let normal = vec(1., 2., 3.).normalized();
+        let spread = |alpha: f64| {
+            let mut rng = Rng::create(3);
+            let mut sum = 0.;
+            for _ in 0..1000 {
+                let h = ggx_half_vector(&normal, alpha, rng.next_f64(), rng.next_f64());
+                assert![(h.norm() - 1.).abs() < 1e-9 && h.dot(normal) > 0.];
+                sum += h.dot(normal);
+            }
+            sum / 1000.
+        };
+        assert![spread(0.01) > 0.999];
+        assert![spread(0.5) < spread(0.1)];
+    }
+
+    #[test]
+    fn test_energy_conservation() {
+        let white = Vec3f::ones();
+        let to_viewer = vec(0.5, 1., 0.).normalized();
+
+        // A white metal loses only what the masking hides. Much more when rough, the light
+        // bouncing several times in between the microfacets is not accounted for
+        let smooth = albedo(&Microfacet::create(1., 0.2), white, to_viewer);
+        let rough = albedo(&Microfacet::create(1., 1.), white, to_viewer);
+        assert![smooth.x > 0.97 && smooth.x <= 1.];
+        assert![rough.x < smooth.x && rough.x > 0.3];
+
+        // The coat and the diffuse base never add up to more than what comes in
+        for roughness in [0.05, 0.5, 1.].iter() {
+            let plastic = albedo(&Microfacet::create(0., *roughness), white, to_viewer);
+            assert![plastic.x <= 1.01 && plastic.x > 0.9];
+        }
+
+        // Rough glass, reflected and refracted
+        let mut glass = Microfacet::create(0., 0.3);
+        glass.transmission = 1.;
+        let total = albedo(&glass, white, to_viewer);
+        assert![total.x <= 1. && total.x > 0.9];
+
+        // Colors
+        let gold = vec(1., 0.78, 0.34);
+        let color = albedo(&Microfacet::create(1., 0.3), gold, to_viewer);
+        assert![color.x > color.y && color.y > color.z];
+    }
+
+    #[test]
+    fn test_sampling_matches_eval() {
+        // The sampled weights and the evaluated reflection agree on the reflected light
+        let base_color = vec(0.9, 0.5, 0.2);
+        let to_viewer = vec(0.5, 1., 0.).normalized();
+        for (metallic, roughness) in [(1., 0.5), (0., 0.5), (0.5, 0.8)].iter() {
+            let microfacet = Microfacet::create(*metallic, *roughness);
+            let sampled = albedo(&microfacet, base_color, to_viewer);
+            let integrated = integrated_albedo(&microfacet, base_color, to_viewer);
+            assert![(sampled - integrated).norm() < 0.02];
+        }
+
+        // Facing the light, a fully rough dielectric without reflection is Lambertian
+        let lambert = Microfacet::create(0., 1.);
+        let light = lambert.eval(base_color, 1., up(), up(), vec(1., 1., 0.).normalized());
+        assert![(light - base_color.scaled((PI / 4.).cos())).norm() < 1e-12];
+
+        // Nothing from below the surface
+        let below = lambert.eval(base_color, 1.5, up(), up(), vec(1., -1., 0.).normalized());
+        assert_eq![below, Vec3f::zero()];
+    }
+
+    #[test]
+    fn test_rough_refraction() {
+        let intersection = Intersection {
+            point: Vec3f::zero(),
+            normal: up(),
+            reflectance: Reflectance::create_default(),
+            barycentric: None,
+            uv: None,
+        };
+        let incident = vec(1., -1., 0.).normalized();
+        let smooth_dir = dielectric_interface(incident, &intersection, 1.5)
+            .transmitted
+            .unwrap()
+            .1;
+
+        // Almost smooth glass bends like a plain interface, whichever the side
+        let mut glass = Microfacet::create(0., 0.01);
+        glass.transmission = 1.;
+        let mut rng = Rng::create(5);
+        let mut n_refracted = 0;
+        for _ in 0..100 {
+            let (dir, weight) = glass
+                .sample(
+                    Vec3f::ones(),
+                    1.5,
+                    up(),
+                    -incident,
+                    rng.next_f64(),
+                    rng.next_f64(),
+                    rng.next_f64(),
+                )
+                .unwrap();
+            if dir.y < 0. {
+                n_refracted += 1;
+                assert![(dir - smooth_dir).norm() < 0.01];
+                assert![(weight.x - 1.).abs() < 0.01];
+
+                // And back out
+                let (back, _) = glass
+                    .sample(Vec3f::ones(), 1.5, up(), dir, 0.99, 0.5, 0.5)
+                    .unwrap();
+                assert![(back + incident).norm() < 0.02];
+            }
+        }
+        assert![n_refracted > 80];
+
+        // The refracted light is tinted by the base color, rougher glass spreads it
+        let mut blue_glass = Microfacet::create(0., 0.4);
+        blue_glass.transmission = 1.;
+        let mut rng = Rng::create(5);
+        let mut spread = 0.;
+        for _ in 0..1000 {
+            let u = 0.5 + 0.5 * rng.next_f64();
+            if let Some((dir, weight)) = blue_glass.sample(
+                vec(0.2, 0.4, 1.),
+                1.5,
+                up(),
+                -incident,
+                u,
+                rng.next_f64(),
+                rng.next_f64(),
+            ) {
+                if dir.y < 0. {
+                    assert![weight.x < weight.z];
+                    spread += (dir - smooth_dir).norm() / 1000.;
+                }
+            }
+        }
+        assert![spread > 0.05];
+
+        // Smooth approximation: nothing refracted by metals, Fresnel split for glass
+        let (reflected, refracted) = glass.mirror_weights(Vec3f::ones(), 1.5, up(), up());
+        assert![(reflected.x - 0.04).abs() < 1e-12 && (refracted.x - 0.96).abs() < 1e-12];
+        let (reflected, refracted) =
+            Microfacet::create(1., 0.).mirror_weights(vec(1., 0.5, 0.), 1.5, up(), up());
+        assert_eq![reflected, vec(1., 0.5, 0.)];
+        assert_eq![refracted, Vec3f::zero()];
+    }
+}
@@ -3,20 +3,31 @@ use obj::rayon::prelude::*;
 
 extern crate tobj;
 
-use geometry::Vec3f;
-// use polygon::*;
 use self::tobj::LoadOptions;
+use bvh::{Bvh, Split};
+use geometry::Vec3f;
+use microfacet::Microfacet;
 use shapes::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::path::Path;
+use std::sync::Arc;
+use texture;
+use texture::Texture;
 use triangle::*;
 
+// Faces angled by more than this are not smoothed together
+const CREASE_ANGLE: f64 = PI / 3.;
+
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct Obj {
     model: tobj::Model, // Model holds a mesh definition and a name
     material: Option<tobj::Material>,
     reflectances: Vec<Reflectance>,
     triangles: Vec<Triangle>,
     bounding_box: BoundingBox,
+    bvh: Bvh, // Over the triangles, built once at load time
 }
 
 #[allow(dead_code)]
@@ -26,6 +37,9 @@ impl Obj {
         for t in &mut self.triangles {
             t.offset(off);
         }
+        self.bounding_box.min += off;
+        self.bounding_box.max += off;
+        self.bvh.offset(off);
     }
 
     pub fn update_bounding_box(&mut self) {
@@ -49,7 +63,7 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
         ignore_lines: true,
     };
 
-    let loaded = tobj::load_obj(&Path::new(&path), &option);
+    let loaded = tobj::load_obj(Path::new(&path), &option);
     if loaded.is_err() {
         println!["Could not load obj from {}", path];
         return None;
@@ -57,23 +71,27 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
 
     let (models, materials) = loaded.unwrap();
 
+    // A missing or broken .mtl file is not fatal, the default reflectance is used
+    let materials = materials.unwrap_or_else(|e| {
+        println!["Could not load the materials for {}: {:?}", path, e];
+        vec![]
+    });
+
     println!["Loaded obj from {}", path];
-    println![
-        "Models {}, materials {}",
-        models.len(),
-        materials.as_ref().expect("WOOPS").len()
-    ];
+    println!["Models {}, materials {}", models.len(), materials.len()];
+
+    // Textures are looked up next to the .obj file, and shared in between materials
+    let directory = Path::new(&path).parent().unwrap_or_else(|| Path::new(""));
+    let textures = load_textures(&materials, directory);
 
     // Construct independent object from the models and materials
     let objects: Vec<Obj> = models
         .into_par_iter()
         .map(|model| {
-            // TODO: Handle material/reflectance properly
-            let material = if let Some(id) = model.mesh.material_id {
-                Some(materials.as_ref().expect("WOOPS")[id].clone())
-            } else {
-                None
-            };
+            let material = model
+                .mesh
+                .material_id
+                .and_then(|id| materials.get(id).cloned());
 
             // Pre compute all the triangles
             let n_triangles = model.mesh.indices.len() / 3;
@@ -92,11 +110,9 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
             });
 
             let triangles: Vec<Triangle> = (0..n_triangles)
-                .into_iter()
                 .map(|t| {
                     // Collect all the vertices for this face
                     let vertices: Vec<Vec3f> = (0..3)
-                        .into_iter()
                         .map(|v| {
                             let i_v = model.mesh.indices[t * 3 + v] as usize;
                             let vertex = Vec3f {
@@ -114,6 +130,32 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
                 })
                 .collect();
 
+            // Smooth shading: use the normals from the file, or generate them
+            let mut triangles = triangles;
+            let normals = if model.mesh.normals.is_empty() {
+                generate_normals(&triangles, &model.mesh.indices)
+            } else {
+                read_normals(&model.mesh.normals, &model.mesh.indices)
+            };
+            for (triangle, normals) in triangles.iter_mut().zip(normals) {
+                triangle.vertex_normals = Some(normals);
+            }
+
+            // Texture coordinates, indexed as the positions (single index mode)
+            if !model.mesh.texcoords.is_empty() {
+                let texcoords = &model.mesh.texcoords;
+                for (triangle, face) in triangles.iter_mut().zip(model.mesh.indices.chunks(3)) {
+                    triangle.texcoords = Some(
+                        face.iter()
+                            .map(|i| {
+                                let i = *i as usize;
+                                (texcoords[2 * i] as f64, texcoords[2 * i + 1] as f64)
+                            })
+                            .collect(),
+                    );
+                }
+            }
+
             println![
                 "Object bounding box: {} - {}. scale {}",
                 bounding_box.min,
@@ -121,21 +163,17 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
                 bounding_box.scale()
             ];
 
-            // Get arbitrary reflectance values, continuous
-            let reflectances: Vec<Reflectance> = (0..n_triangles)
-                .into_iter()
-                .map(|t| {
-                    let mut r = Reflectance::create_default();
-                    let t_f = t as f64;
-                    r.diffuse_color = Vec3f {
-                        x: 1. - t_f / n_triangles as f64,
-                        y: t_f / n_triangles as f64,
-                        z: 1.,
-                    };
-                    println!["Object diffuse color {}", r.diffuse_color];
-                    r
-                })
-                .collect();
+            // tobj splits the meshes per material, all the faces share the same one
+            let reflectance = match material {
+                Some(ref material) => reflectance_from_material(material, &textures),
+                None => Reflectance::create_default(),
+            };
+            let reflectances = vec![reflectance; n_triangles];
+
+            // Acceleration structure over the triangles
+            let triangle_boxes: Vec<BoundingBox> =
+                triangles.iter().map(|t| t.bounding_box()).collect();
+            let bvh = Bvh::create_with_split(&triangle_boxes, Split::Sah);
 
             Obj {
                 model,
@@ -143,6 +181,7 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
                 reflectances,
                 triangles,
                 bounding_box,
+                bvh,
             }
         })
         .collect();
@@ -150,6 +189,205 @@ pub fn load(path: String) -> Option<Vec<Obj>> {
     Some(objects)
 }
 
+// Normals given in the file, indexed as the positions (single index mode)
+fn read_normals(normals: &[f32], indices: &[u32]) -> Vec<Vec<Vec3f>> {
+    indices
+        .chunks(3)
+        .map(|face| {
+            face.iter()
+                .map(|i| {
+                    let i = *i as usize;
+                    Vec3f {
+                        x: normals[3 * i] as f64,
+                        y: normals[3 * i + 1] as f64,
+                        z: normals[3 * i + 2] as f64,
+                    }
+                    .normalized()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Angle-weighted vertex normals, per face corner. Only the faces angled by less
+// than the crease angle are averaged, so that the edges of boxes stay sharp
+fn generate_normals(triangles: &[Triangle], indices: &[u32]) -> Vec<Vec<Vec3f>> {
+    let crease_cos = CREASE_ANGLE.cos();
+
+    // Angle of every face corner
+    let angles: Vec<f64> = triangles
+        .iter()
+        .flat_map(|t| {
+            (0..3).map(move |c| {
+                let e1 = (t.vertices[(c + 1) % 3] - t.vertices[c]).normalized();
+                let e2 = (t.vertices[(c + 2) % 3] - t.vertices[c]).normalized();
+                e1.dot(e2).clamp(-1., 1.).acos()
+            })
+        })
+        .collect();
+
+    // Face corners touching every vertex
+    let n_vertices = indices.iter().max().map_or(0, |i| *i as usize + 1);
+    let mut corners: Vec<Vec<usize>> = vec![vec![]; n_vertices];
+    for (corner, i) in indices.iter().enumerate() {
+        corners[*i as usize].push(corner);
+    }
+
+    triangles
+        .iter()
+        .enumerate()
+        .map(|(t, triangle)| {
+            (0..3)
+                .map(|c| {
+                    let mut normal = Vec3f::zero();
+                    for corner in &corners[indices[3 * t + c] as usize] {
+                        let face_normal = triangles[corner / 3].normal;
+                        if face_normal.dot(triangle.normal) >= crease_cos {
+                            normal += face_normal.scaled(angles[*corner]);
+                        }
+                    }
+
+                    if normal.squared_norm() > 0. {
+                        normal.normalized()
+                    } else {
+                        triangle.normal
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Load all the textures referenced by the materials, once
+fn load_textures(
+    materials: &[tobj::Material],
+    directory: &Path,
+) -> HashMap<String, Arc<dyn Texture>> {
+    let mut textures = HashMap::new();
+
+    for material in materials {
+        for name in material
+            .diffuse_texture
+            .iter()
+            .chain(material.specular_texture.iter())
+        {
+            if textures.contains_key(name) {
+                continue;
+            }
+
+            let texture_path = directory.join(name).to_string_lossy().into_owned();
+            match texture::load(&texture_path) {
+                Ok(texture) => {
+                    textures.insert(name.clone(), Arc::new(texture) as Arc<dyn Texture>);
+                }
+                Err(e) => eprintln!["{}", e],
+            }
+        }
+    }
+    textures
+}
+
+// Translate the .mtl fields into our reflectance model,
+// see http://paulbourke.net/dataformats/mtl/ for the meaning of each field
+pub fn reflectance_from_material(
+    material: &tobj::Material,
+    textures: &HashMap<String, Arc<dyn Texture>>,
+) -> Reflectance {
+    let to_vec = |c: [f32; 3]| Vec3f {
+        x: c[0] as f64,
+        y: c[1] as f64,
+        z: c[2] as f64,
+    };
+
+    let mut reflectance = Reflectance::create_default();
+
+    // Kd
+    if let Some(diffuse) = material.diffuse {
+        reflectance.diffuse_color = to_vec(diffuse);
+    }
+
+    // map_Kd and map_Ks, textures which could not be loaded are skipped
+    let find_texture = |name: &Option<String>| name.as_ref().and_then(|n| textures.get(n).cloned());
+    reflectance.diffuse_texture = find_texture(&material.diffuse_texture);
+    reflectance.specular_texture = find_texture(&material.specular_texture);
+
+    // Ks and Ns, our specular model is monochromatic
+    if let Some(specular) = material.specular {
+        reflectance.specular = to_vec(specular).max();
+    }
+    if let Some(shininess) = material.shininess {
+        if shininess > 0. {
+            reflectance.specular_exponent = shininess as f64;
+        }
+    }
+
+    // Ke. Ka is an ambient color, often a copy of Kd, and does not make a light
+    if let Some(emission) = material.emissive {
+        reflectance.emission = to_vec(emission);
+    }
+
+    // Ni
+    if let Some(index) = material.optical_density {
+        if index > 0. {
+            reflectance.refractive_index = index as f64;
+        }
+    }
+
+    // Tf, the share of each channel going through one unit of distance inside the material.
+    // Only the plain RGB form, the spectral and XYZ ones are ignored
+    let filter: Vec<f64> = material
+        .unknown_param
+        .get("Tf")
+        .map(|tf| {
+            tf.split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    if filter.len() == 3 {
+        let absorption = |t: f64| -t.clamp(1e-6, 1.).ln();
+        reflectance.absorption = Vec3f {
+            x: absorption(filter[0]),
+            y: absorption(filter[1]),
+            z: absorption(filter[2]),
+        };
+    }
+
+    // d and illum: transparent or mirror-like materials
+    let dissolve = material.dissolve.unwrap_or(1.).clamp(0., 1.) as f64;
+    let illum = material.illumination_model.unwrap_or(2);
+
+    if dissolve < 1. || [4, 6, 7, 9].contains(&illum) {
+        // Whatever is not dissolved is reflected
+        reflectance.is_glass_like = true;
+        reflectance.reflection = dissolve.min(reflectance.reflection);
+    } else if illum == 3 || illum == 5 {
+        // Pure mirror
+        reflectance.is_glass_like = true;
+        reflectance.reflection = 1.;
+    }
+
+    // Pr and Pm, from the PBR extension: a physically based material instead.
+    // Whatever is not dissolved is refracted, plain glass by default
+    let parameter = |key: &str| {
+        material
+            .unknown_param
+            .get(key)
+            .and_then(|v| v.trim().parse::<f64>().ok())
+    };
+    let (roughness, metallic) = (parameter("Pr"), parameter("Pm"));
+    if roughness.is_some() || metallic.is_some() {
+        let mut microfacet = Microfacet::create(metallic.unwrap_or(0.), roughness.unwrap_or(0.5));
+        microfacet.transmission = 1. - dissolve;
+        reflectance.microfacet = Some(microfacet);
+        if material.optical_density.is_none() {
+            reflectance.refractive_index = 1.5;
+        }
+    }
+
+    reflectance
+}
+
 #[allow(dead_code)]
 pub fn autoscale(objects: &mut Vec<Obj>, desired_scale: f64) {
     assert_ne![desired_scale, 0.];
@@ -165,9 +403,7 @@ pub fn autoscale(objects: &mut Vec<Obj>, desired_scale: f64) {
     // Scale all the vertices
     if bb.scale() > 0. {
         for o in &mut (*objects) {
-            for v in &mut o.triangles {
-                v.offset(-bb.middle());
-            }
+            o.offset(-bb.middle());
         }
     }
 
@@ -184,35 +420,14 @@ pub fn autoscale(objects: &mut Vec<Obj>, desired_scale: f64) {
 
 impl Shape for Obj {
     fn intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<Intersection> {
-        let mut intersection_final = Intersection::create_default();
-
-        let mut hit_triangle = false;
-        let mut dist_closest = 0.;
-
-        // Go through all triangles, return the hit closest to ray origin
-        for (i, t) in self.triangles.iter().enumerate() {
-            let res = t.intersect(orig, dir);
-
-            if let Some(intersection) = res {
-                let dist_hit = (intersection.point - *orig).squared_norm();
-                if !hit_triangle || dist_hit < dist_closest {
-                    // FIXME: we don't handle per-triangle color here, a bit broken
-                    intersection_final = Intersection {
-                        point: intersection.point,
-                        normal: intersection.normal,
-                        reflectance: self.reflectances[i],
-                    };
-
-                    hit_triangle = true;
-                    dist_closest = dist_hit;
-                }
-            }
-        }
-
-        if hit_triangle {
-            return Some(intersection_final);
-        }
-        None
+        // Go through the triangles whose bounding boxes are hit,
+        // return the hit closest to ray origin
+        self.bvh
+            .closest_hit(orig, dir, |i| self.triangles[i].intersect(orig, dir))
+            .map(|(intersection, i)| Intersection {
+                reflectance: self.reflectances[i].clone(),
+                ..intersection
+            })
     }
 
     fn bounding_box(&self) -> BoundingBox {
@@ -225,10 +440,305 @@ impl Shape for Obj {
 #[cfg(test)]
 mod test {
     use super::*;
+    use framebuffer;
 
     #[test]
     fn load_cornell_box() {
         let test = load(String::from("../test_data/cornell_box.obj"));
         assert![test.is_some()];
     }
+
+    #[test]
+    fn test_cornell_box_materials() {
+        let objects = load(String::from("../test_data/cornell_box.obj")).unwrap();
+
+        let reflectance = |name: &str| {
+            objects
+                .iter()
+                .find(|o| o.model.name == name)
+                .map(|o| o.reflectances[0].clone())
+                .unwrap()
+        };
+
+        assert_eq![reflectance("floor").diffuse_color, Vec3f::ones()];
+        assert_eq![
+            reflectance("red_wall").diffuse_color,
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: 0.
+            }
+        ];
+        assert_eq![
+            reflectance("green_wall").diffuse_color,
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: 0.
+            }
+        ];
+
+        // Ks 0 0 0 everywhere, no specular highlights
+        assert_eq![reflectance("red_wall").specular, 0.];
+
+        // The light is emissive (Ke 20), semi transparent (d 0.8) and has an index (Ni 1.25)
+        let light = reflectance("light");
+        assert_eq![light.emission, Vec3f::ones().scaled(20.)];
+        assert![light.is_glass_like];
+        assert![(light.reflection - 0.8).abs() < 1e-6];
+        assert_eq![light.refractive_index, 1.25];
+
+        // All the faces of a mesh share the material
+        for o in &objects {
+            assert_eq![o.reflectances.len(), o.triangles.len()];
+        }
+    }
+
+    #[test]
+    fn test_material_translation() {
+        let mut material = tobj::Material {
+            diffuse: Some([0.5, 0.25, 1.]),
+            specular: Some([0.2, 0.6, 0.4]),
+            shininess: Some(10.),
+            emissive: Some([0.1, 0.2, 0.3]),
+            ambient: Some([5., 5., 5.]),
+            ..Default::default()
+        };
+
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert_eq![
+            r.diffuse_color,
+            Vec3f {
+                x: 0.5,
+                y: 0.25,
+                z: 1.
+            }
+        ];
+        assert![(r.specular - 0.6).abs() < 1e-6];
+        assert_eq![r.specular_exponent, 10.];
+        assert![(r.emission.z - 0.3).abs() < 1e-6];
+        assert![!r.is_glass_like];
+
+        // Ambient only, not a light
+        let ambient = tobj::Material {
+            emissive: None,
+            ..material.clone()
+        };
+        assert_eq![
+            reflectance_from_material(&ambient, &HashMap::new()).emission,
+            Vec3f::zero()
+        ];
+
+        // Mirror
+        material.illumination_model = Some(3);
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert![r.is_glass_like];
+        assert_eq![r.reflection, 1.];
+
+        // Refractive glass
+        material.illumination_model = Some(7);
+        material.optical_density = Some(1.5);
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert![r.is_glass_like];
+        assert_eq![r.refractive_index, 1.5];
+        assert_eq![r.absorption, Vec3f::zero()];
+
+        // Tinted glass, half of the red light goes through a unit of distance
+        material
+            .unknown_param
+            .insert(String::from("Tf"), String::from("0.5 1 1"));
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert![(r.transmittance(1.).x - 0.5).abs() < 1e-9];
+        assert_eq![r.transmittance(1.).y, 1.];
+        material
+            .unknown_param
+            .insert(String::from("Tf"), String::from("spectral glass.rfl"));
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert_eq![r.absorption, Vec3f::zero()];
+        assert![r.microfacet.is_none()];
+
+        // Physically based, mostly transparent
+        let param = |key: &str, value: &str| (String::from(key), String::from(value));
+        let material = tobj::Material {
+            diffuse: Some([0.9, 0.6, 0.2]),
+            dissolve: Some(0.25),
+            unknown_param: vec![param("Pm", "0.5"), param("Pr", " 0.3")]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let r = reflectance_from_material(&material, &HashMap::new());
+        assert_eq![
+            r.microfacet,
+            Some(Microfacet {
+                metallic: 0.5,
+                roughness: 0.3,
+                transmission: 0.75
+            })
+        ];
+        assert_eq![r.refractive_index, 1.5];
+    }
+
+    #[test]
+    fn test_generated_normals() {
+        // Two faces folded by a small angle along the y axis, and a third one
+        // sharing the edge at a right angle
+        let p = |x: f64, y: f64, z: f64| Vec3f { x, y, z };
+        let triangles = vec![
+            Triangle::create(vec![p(0., 0., 0.), p(0., 1., 0.), p(-1., 0., -0.2)]),
+            Triangle::create(vec![p(0., 1., 0.), p(0., 0., 0.), p(1., 0., -0.2)]),
+            Triangle::create(vec![p(0., 0., 0.), p(0., 0., -1.), p(0., 1., 0.)]),
+        ];
+        let indices = vec![0, 1, 2, 1, 0, 3, 0, 4, 1];
+
+        let normals = generate_normals(&triangles, &indices);
+
+        // On the shared edge, the two first faces are averaged
+        let shared = (triangles[0].normal + triangles[1].normal).normalized();
+        assert![(normals[0][0] - shared).squared_norm() < 1e-9];
+        assert![(normals[1][1] - shared).squared_norm() < 1e-9];
+        assert![(normals[0][0].z - 1.).abs() < 1e-9];
+
+        // Away from it, each face keeps its own normal
+        assert![(normals[0][2] - triangles[0].normal).squared_norm() < 1e-9];
+
+        // The third face is beyond the crease angle, stays flat
+        for n in &normals[2] {
+            assert![(*n - triangles[2].normal).squared_norm() < 1e-9];
+        }
+    }
+
+    #[test]
+    fn test_flat_dodecahedron() {
+        // Dihedral angle is bigger than the crease angle, faces are kept flat
+        let objects = load(String::from("../test_data/dodecahedron.obj")).unwrap();
+        for t in &objects[0].triangles {
+            for n in t.vertex_normals.as_ref().unwrap() {
+                assert![(*n - t.normal).squared_norm() < 1e-9];
+            }
+        }
+    }
+
+    #[test]
+    fn test_textured_material() {
+        // Write a small texture next to a material referencing it
+        let directory = std::env::temp_dir();
+        let mut fb = framebuffer::create_frame_buffer(2, 2);
+        fb.buffer[0][0] = Vec3f::ones();
+        fb.write_ppm(directory.join("rusty_marcher_map.ppm").to_str().unwrap())
+            .unwrap();
+
+        let material = tobj::Material {
+            diffuse: Some([1., 0.5, 1.]),
+            diffuse_texture: Some(String::from("rusty_marcher_map.ppm")),
+            specular_texture: Some(String::from("missing_map.ppm")),
+            ..Default::default()
+        };
+
+        let textures = load_textures(std::slice::from_ref(&material), &directory);
+        assert_eq![textures.len(), 1];
+
+        let r = reflectance_from_material(&material, &textures);
+        assert![r.specular_texture.is_none()];
+
+        // The texture modulates the diffuse color, top left is white, bottom left black
+        let mut intersection = Intersection::create_default();
+        intersection.reflectance = r;
+        intersection.uv = Some((0.25, 0.75));
+        assert_eq![
+            intersection.diffuse_color(),
+            Vec3f {
+                x: 1.,
+                y: 0.5,
+                z: 1.
+            }
+        ];
+        intersection.uv = Some((0.25, 0.25));
+        assert_eq![intersection.diffuse_color(), Vec3f::zero()];
+    }
+
+    #[test]
+    fn test_default_material() {
+        // No .mtl file for this one
+        let objects = load(String::from("../test_data/dodecahedron.obj")).unwrap();
+        let default = Reflectance::create_default();
+        assert_eq![
+            objects[0].reflectances[0].diffuse_color,
+            default.diffuse_color
+        ];
+        assert![!objects[0].reflectances[0].is_glass_like];
+    }
+
+    #[test]
+    fn test_bvh_matches_linear() {
+        let mut objects = load(String::from("../test_data/cornell_box.obj")).unwrap();
+        objects.append(&mut load(String::from("../test_data/dodecahedron.obj")).unwrap());
+
+        let orig = Vec3f {
+            x: 278.,
+            y: 273.,
+            z: -800.,
+        };
+
+        for o in &objects {
+            for i in 0..30 {
+                for j in 0..30 {
+                    let dir = Vec3f {
+                        x: i as f64 / 15. - 1.,
+                        y: j as f64 / 15. - 1.,
+                        z: 1.5,
+                    }
+                    .normalized();
+
+                    // Brute force closest hit over all the triangles
+                    let linear = o
+                        .triangles
+                        .iter()
+                        .filter_map(|t| t.intersect(&orig, &dir))
+                        .map(|hit| (hit.point - orig).squared_norm())
+                        .fold(None, |closest: Option<f64>, d| {
+                            Some(closest.map_or(d, |c| c.min(d)))
+                        });
+
+                    let accelerated = o
+                        .intersect(&orig, &dir)
+                        .map(|hit| (hit.point - orig).squared_norm());
+
+                    assert_eq![linear.is_some(), accelerated.is_some()];
+                    if let (Some(l), Some(a)) = (linear, accelerated) {
+                        assert![(l - a).abs() < 1e-6];
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_offset() {
+        let mut objects = load(String::from("../test_data/dodecahedron.obj")).unwrap();
+        let o = &mut objects[0];
+
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        let orig = o.bounding_box().middle()
+            + Vec3f {
+                x: 10.,
+                y: 5.,
+                z: 500.,
+            };
+        assert![o.intersect(&orig, &dir).is_some()];
+
+        // Move the object out of the way, the bounding volumes need to follow
+        let off = Vec3f {
+            x: 500.,
+            y: 0.,
+            z: 0.,
+        };
+        o.offset(off);
+        assert![o.intersect(&orig, &dir).is_none()];
+        assert![o.intersect(&(orig + off), &dir).is_some()];
+    }
 }
@@ -1,133 +1,338 @@
 use geometry::Vec3f;
 use shapes::Intersection;
 
-pub fn reflect(incident: Vec3f, normal: Vec3f) -> Vec3f {
-    incident - normal.scaled(2. * incident.dot(normal))
+// Wavelength dependent refractive index, which splits the white light in colours
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    Rgb(Vec3f),                // One index per color channel
+    Cauchy { a: f64, b: f64 }, // n = a + b / wavelength^2, wavelength in micrometers
 }
 
-pub fn reflect_ray(
-    incident: Vec3f,
-    intersection: &Intersection,
-    refractive_index: f64,
-) -> Option<(Vec3f, Vec3f)> {
-    // Compute incident angle
-    // See https://en.wikipedia.org/wiki/Snell%27s_law
-    let mut normal = intersection.normal;
-    let mut c = normal.dot(incident);
+// Wavelengths standing for the red, green and blue channels, in micrometers
+pub const RGB_WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
 
-    // Could be that the ray is inside the object
-    let r = if c < 0. {
-        refractive_index
-    } else {
-        1. / refractive_index
-    };
+// See https://en.wikipedia.org/wiki/Cauchy%27s_equation
+pub fn cauchy(a: f64, b: f64, wavelength: f64) -> f64 {
+    a + b / (wavelength * wavelength)
+}
 
-    if c < 0. {
-        c = -c;
-        normal = -normal;
+impl Dispersion {
+    // Refractive index of every color channel
+    pub fn indices(&self) -> Vec3f {
+        match self {
+            Dispersion::Rgb(indices) => *indices,
+            Dispersion::Cauchy { a, b } => Vec3f {
+                x: cauchy(*a, *b, RGB_WAVELENGTHS[0]),
+                y: cauchy(*a, *b, RGB_WAVELENGTHS[1]),
+                z: cauchy(*a, *b, RGB_WAVELENGTHS[2]),
+            },
+        }
     }
 
-    let cos_theta_2 = 1. - r * r * (1. - c * c);
-
-    // Total refraction, no reflection
-    if cos_theta_2 > 0. {
-        return None;
+    // Refractive index at any wavelength, in micrometers. The per channel indices
+    // are linearly interpolated, and constant past the red and the blue
+    pub fn index_at(&self, wavelength: f64) -> f64 {
+        match self {
+            Dispersion::Rgb(indices) => {
+                let [red, green, blue] = RGB_WAVELENGTHS;
+                if wavelength >= red {
+                    indices.x
+                } else if wavelength >= green {
+                    indices.y + (indices.x - indices.y) * (wavelength - green) / (red - green)
+                } else if wavelength >= blue {
+                    indices.z + (indices.y - indices.z) * (wavelength - blue) / (green - blue)
+                } else {
+                    indices.z
+                }
+            }
+            Dispersion::Cauchy { a, b } => cauchy(*a, *b, wavelength),
+        }
     }
+}
 
-    // We got a reflection, return the ray + an origin offset from the original shape
-    let reflected_ray = reflect(incident, normal);
-    let reflection_orig: Vec3f;
+pub fn reflect(incident: Vec3f, normal: Vec3f) -> Vec3f {
+    incident - normal.scaled(2. * incident.dot(normal))
+}
 
-    if reflected_ray.dot(intersection.normal) < 0. {
-        reflection_orig = intersection.point - intersection.normal.scaled(1e-4);
-    } else {
-        reflection_orig = intersection.point + intersection.normal.scaled(1e-4);
-    };
+// Share of the light reflected by a smooth interface, unpolarized, going from a medium
+// of index n1 into a medium of index n2. Exact Fresnel equations, total internal
+// reflection past the critical angle
+// See https://en.wikipedia.org/wiki/Fresnel_equations
+pub fn fresnel(cos_incident: f64, n1: f64, n2: f64) -> f64 {
+    let cos_i = cos_incident.abs().min(1.);
+    let sin_t = n1 / n2 * (1. - cos_i * cos_i).max(0.).sqrt();
+    if sin_t >= 1. {
+        return 1.;
+    }
 
-    Some((reflection_orig, reflected_ray))
+    let cos_t = (1. - sin_t * sin_t).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n2 * cos_i - n1 * cos_t) / (n2 * cos_i + n1 * cos_t);
+    (rs * rs + rp * rp) / 2.
 }
 
-pub fn refract_ray(
+// What a ray becomes when it hits a smooth interface in between two dielectrics:
+// a reflected ray, a transmitted ray unless it is totally reflected, and the share
+// of the light going each way. The normal of the intersection points towards the
+// outside medium (air), the ray can come from either side
+#[derive(Clone, Copy, Debug)]
+pub struct Interface {
+    pub entering: bool,                      // Coming from the outside
+    pub eta: f64, // Refractive index on the incident side over the one on the other side
+    pub reflected: (Vec3f, Vec3f), // Origin and direction, offset from the surface
+    pub transmitted: Option<(Vec3f, Vec3f)>, // None under total internal reflection
+    pub reflected_weight: f64, // Fresnel, 1 under total internal reflection
+    pub transmitted_weight: f64,
+}
+
+pub fn dielectric_interface(
     incident: Vec3f,
     intersection: &Intersection,
     refractive_index: f64,
-) -> Option<(Vec3f, Vec3f)> {
+) -> Interface {
     // See https://en.wikipedia.org/wiki/Snell%27s_law
-    let mut normal = intersection.normal;
-    let mut c = -normal.dot(incident);
+    let incident = incident.normalized();
+    let entering = incident.dot(intersection.normal) <= 0.;
 
-    // Could be that the ray is inside the object
-    let r = if c < 0. {
-        refractive_index
+    // Normal on the incident side
+    let (normal, eta) = if entering {
+        (intersection.normal, 1. / refractive_index)
     } else {
-        1. / refractive_index
+        (-intersection.normal, refractive_index)
     };
+    let cos_i = -incident.dot(normal);
 
-    if c < 0. {
-        c = -c;
-        normal = -normal;
-    }
+    let reflected = (
+        intersection.point + normal.scaled(1e-4),
+        reflect(incident, normal),
+    );
 
-    let cos_theta_2 = 1. - r * r * (1. - c * c);
-
-    // Total reflection, no refraction
-    if cos_theta_2 < 0. {
-        return None;
+    // Past the critical angle, everything is reflected
+    let cos_t2 = 1. - eta * eta * (1. - cos_i * cos_i);
+    if cos_t2 <= 0. {
+        return Interface {
+            entering,
+            eta,
+            reflected,
+            transmitted: None,
+            reflected_weight: 1.,
+            transmitted_weight: 0.,
+        };
     }
 
-    let refracted_ray =
-        (incident.scaled(r) + normal.scaled(r * c - cos_theta_2.sqrt())).normalized();
+    let transmitted = (
+        intersection.point - normal.scaled(1e-4),
+        (incident.scaled(eta) + normal.scaled(eta * cos_i - cos_t2.sqrt())).normalized(),
+    );
+    let reflected_weight = fresnel(cos_i, eta, 1.);
 
-    // Compute the ray origin, offset from the origin shape
-    let refract_orig: Vec3f = if refracted_ray.dot(normal) > 0. {
-        intersection.point + normal.scaled(1e-4)
-    } else {
-        intersection.point - normal.scaled(1e-4)
-    };
-
-    Some((refract_orig, refracted_ray))
+    Interface {
+        entering,
+        eta,
+        reflected,
+        transmitted: Some(transmitted),
+        reflected_weight,
+        transmitted_weight: 1. - reflected_weight,
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use shapes::Reflectance;
+    use std::f64::consts::PI;
 
-    #[test]
-    fn test_reflection() {
-        let incident = Vec3f {
-            x: 0.5,
-            y: -0.5,
-            z: 0.,
-        };
+    fn vec(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    fn close(a: Vec3f, b: Vec3f) -> bool {
+        (a - b).norm() < 1e-9
+    }
 
-        let intersection = Intersection {
+    // Horizontal surface at the origin, the outside is above
+    fn horizontal_surface() -> Intersection {
+        Intersection {
             point: Vec3f::zero(),
-            normal: Vec3f {
-                x: 0.,
-                y: 1.,
-                z: 0.,
-            },
+            normal: vec(0., 1., 0.),
             reflectance: Reflectance::create_default(),
-        };
+            barycentric: None,
+            uv: None,
+        }
+    }
 
-        let reference_ray = Vec3f {
-            x: 0.5,
-            y: 0.5,
-            z: 0.,
+    // Direction going down (or up) at a given angle from the vertical
+    fn at_angle(angle: f64, down: bool) -> Vec3f {
+        let y = if down { -angle.cos() } else { angle.cos() };
+        vec(angle.sin(), y, 0.)
+    }
+
+    #[test]
+    fn test_reflection() {
+        let incident = vec(0.5, -0.5, 0.);
+        assert_eq![reflect(incident, vec(0., 1., 0.)), vec(0.5, 0.5, 0.)];
+
+        // Ordinary glass reflects too, not only past the critical angle
+        let interface = dielectric_interface(incident, &horizontal_surface(), 1.5);
+        assert![close(interface.reflected.1, vec(0.5, 0.5, 0.).normalized())];
+        assert![interface.reflected.0.y > 0.];
+        assert![interface.transmitted.is_some()];
+    }
+
+    #[test]
+    fn test_air_to_glass() {
+        let surface = horizontal_surface();
+
+        // Head on, straight through
+        let interface = dielectric_interface(vec(0., -1., 0.), &surface, 1.5);
+        assert![interface.entering];
+        assert![(interface.eta - 1. / 1.5).abs() < 1e-12];
+        assert![(interface.reflected_weight - 0.04).abs() < 1e-12];
+        assert![(interface.transmitted_weight - 0.96).abs() < 1e-12];
+        let (origin, dir) = interface.transmitted.unwrap();
+        assert![origin.y < 0. && close(dir, vec(0., -1., 0.))];
+        assert![close(interface.reflected.1, vec(0., 1., 0.))];
+
+        // 45 degrees, bends towards the normal following Snell's law
+        let incident = at_angle(PI / 4., true);
+        let interface = dielectric_interface(incident, &surface, 1.5);
+        let (_, dir) = interface.transmitted.unwrap();
+        assert![dir.y < 0. && ((PI / 4.).sin() / dir.x - 1.5).abs() < 1e-9];
+        assert![(interface.reflected_weight - 0.0502).abs() < 1e-4];
+        assert_eq![
+            interface.reflected_weight + interface.transmitted_weight,
+            1.
+        ];
+        assert![close(interface.reflected.1, at_angle(PI / 4., false))];
+
+        // The incident direction does not need to be normalized
+        let scaled = dielectric_interface(incident.scaled(3.), &surface, 1.5);
+        assert![close(scaled.transmitted.unwrap().1, dir)];
+
+        // Same index on both sides, nothing changes
+        let interface = dielectric_interface(incident, &surface, 1.);
+        assert![close(interface.transmitted.unwrap().1, incident)];
+        assert![interface.reflected_weight < 1e-12];
+    }
+
+    #[test]
+    fn test_glass_to_air() {
+        let surface = horizontal_surface();
+
+        // 30 degrees from inside, bends away from the normal
+        let interface = dielectric_interface(at_angle(PI / 6., false), &surface, 1.5);
+        assert![!interface.entering];
+        assert_eq![interface.eta, 1.5];
+        let (origin, dir) = interface.transmitted.unwrap();
+        assert![origin.y > 0. && dir.y > 0.];
+        assert![(dir.x - 1.5 * (PI / 6.).sin()).abs() < 1e-9];
+
+        // Reflected back inside
+        assert![interface.reflected.0.y < 0.];
+        assert![close(interface.reflected.1, at_angle(PI / 6., true))];
+
+        // Reversible: the same weights as going the other way along the same path
+        let back = dielectric_interface(-dir, &surface, 1.5);
+        assert![close(
+            back.transmitted.unwrap().1,
+            -at_angle(PI / 6., false)
+        )];
+        assert![(back.reflected_weight - interface.reflected_weight).abs() < 1e-12];
+    }
+
+    #[test]
+    fn test_critical_angle() {
+        // 41.8 degrees for glass
+        let surface = horizontal_surface();
+        let critical = (1. / 1.5f64).asin();
+
+        // Just below, grazing transmitted ray and most of the light reflected
+        let interface = dielectric_interface(at_angle(critical - 1e-3, false), &surface, 1.5);
+        let (_, dir) = interface.transmitted.unwrap();
+        assert![dir.y > 0. && dir.y < 0.1];
+        assert![interface.reflected_weight > 0.5 && interface.reflected_weight < 1.];
+
+        // Just above, total internal reflection
+        let interface = dielectric_interface(at_angle(critical + 1e-3, false), &surface, 1.5);
+        assert![interface.transmitted.is_none()];
+        assert_eq![interface.reflected_weight, 1.];
+        assert_eq![interface.transmitted_weight, 0.];
+        assert![close(
+            interface.reflected.1,
+            at_angle(critical + 1e-3, true)
+        )];
+
+        // Never from the outside, even at grazing incidence
+        let interface = dielectric_interface(at_angle(PI / 2. - 1e-3, true), &surface, 1.5);
+        assert![interface.transmitted.is_some()];
+    }
+
+    #[test]
+    fn test_fresnel() {
+        // Air to glass, head on: ((n - 1) / (n + 1))^2
+        assert![(fresnel(1., 1., 1.5) - 0.04).abs() < 1e-12];
+
+        // 45 degrees, Rs = 0.0920 and Rp = 0.0085
+        let cos_45 = (PI / 4.).cos();
+        assert![(fresnel(cos_45, 1., 1.5) - 0.0502).abs() < 1e-4];
+
+        // Brewster's angle, the p polarization is fully transmitted
+        let brewster = (1.5f64).atan();
+        let cos_t = (PI / 2. - brewster).cos();
+        let rs = (brewster.cos() - 1.5 * cos_t) / (brewster.cos() + 1.5 * cos_t);
+        assert![(fresnel(brewster.cos(), 1., 1.5) - rs * rs / 2.).abs() < 1e-12];
+
+        // Grazing incidence, everything is reflected
+        assert![(fresnel(1e-9, 1., 1.5) - 1.).abs() < 1e-6];
+        assert![fresnel(0.1, 1., 1.5) > fresnel(0.5, 1., 1.5)];
+
+        // Same reflectance both ways, on either side of the interface
+        let cos_t = (1. - (1. - cos_45 * cos_45) / 2.25).sqrt();
+        assert![(fresnel(cos_t, 1.5, 1.) - fresnel(cos_45, 1., 1.5)).abs() < 1e-12];
+
+        // Total internal reflection past the critical angle, 41.8 degrees for glass
+        let critical = (1. / 1.5f64).asin();
+        assert_eq![fresnel((critical + 0.01).cos(), 1.5, 1.), 1.];
+        assert![fresnel((critical - 0.01).cos(), 1.5, 1.) < 1.];
+
+        // No index contrast, no reflection
+        assert![fresnel(0.3, 1.33, 1.33) < 1e-12];
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // BK7 glass, blue light bends more than red light
+        let bk7 = Dispersion::Cauchy {
+            a: 1.5046,
+            b: 0.0042,
         };
+        let indices = bk7.indices();
+        assert![(indices.y - 1.5185).abs() < 1e-4];
+        assert![indices.x < indices.y && indices.y < indices.z];
 
-        assert_eq![reflect(incident, intersection.normal), reference_ray];
+        let rgb = Vec3f {
+            x: 1.5,
+            y: 1.52,
+            z: 1.54,
+        };
+        assert_eq![Dispersion::Rgb(rgb).indices(), rgb];
 
-        let result = reflect_ray(incident, &intersection, 1.5);
+        // In between the channels
+        assert_eq![Dispersion::Rgb(rgb).index_at(0.7), 1.5];
+        assert![(Dispersion::Rgb(rgb).index_at(0.5) - 1.53).abs() < 1e-12];
+        assert_eq![bk7.index_at(0.55), indices.y];
 
-        match result {
-            Some(reflected_ray) => {
-                assert_eq![reflected_ray.1, reference_ray];
-            }
-            None => {
-                assert![false];
-            }
-        }
+        // Through a glass surface at 45 degrees
+        let intersection = horizontal_surface();
+        let incident = at_angle(PI / 4., true);
+        let refracted = |c: usize| {
+            dielectric_interface(incident, &intersection, rgb.channel(c))
+                .transmitted
+                .unwrap()
+                .1
+        };
+        assert![refracted(0).x > refracted(1).x && refracted(1).x > refracted(2).x];
+        let sin_blue = refracted(2).x;
+        assert![((PI / 4.).sin() / sin_blue - 1.54).abs() < 1e-9];
     }
 }
@@ -9,6 +9,11 @@ pub struct ConvexPolygon {
     plane_normal: Vec3f,
     plane_point: Vec3f,
     bounding_box: BoundingBox,
+
+    // Planar texture mapping, the polygon extent maps to [0, 1]
+    uv_origin: Vec3f,
+    u_axis: Vec3f,
+    v_axis: Vec3f,
 }
 
 #[allow(dead_code)]
@@ -32,10 +37,30 @@ impl ConvexPolygon {
         let edge_1 = vertices[1] - vertices[0];
         let edge_2 = vertices[2] - vertices[1];
 
+        let plane_normal = edge_1.cross(edge_2).normalized();
+
+        // - Texture axes in the plane, aligned with the first edge
+        let u_dir = edge_1.normalized();
+        let v_dir = plane_normal.cross(u_dir);
+        let project = |v: &Vec3f| ((*v - vertices[0]).dot(u_dir), (*v - vertices[0]).dot(v_dir));
+
+        let (mut u_min, mut v_min) = project(&vertices[0]);
+        let (mut u_max, mut v_max) = (u_min, v_min);
+        for v in &vertices {
+            let (u, v) = project(v);
+            u_min = u_min.min(u);
+            u_max = u_max.max(u);
+            v_min = v_min.min(v);
+            v_max = v_max.max(v);
+        }
+
         ConvexPolygon {
+            uv_origin: vertices[0] + u_dir.scaled(u_min) + v_dir.scaled(v_min),
+            u_axis: u_dir.scaled(1. / (u_max - u_min)),
+            v_axis: v_dir.scaled(1. / (v_max - v_min)),
             vertices,
             reflectance,
-            plane_normal: edge_1.cross(edge_2).normalized(),
+            plane_normal,
             plane_point: mean,
             bounding_box,
         }
@@ -43,16 +68,20 @@ impl ConvexPolygon {
 
     pub fn offset(&mut self, off: Vec3f) {
         self.plane_point += off;
+        self.uv_origin += off;
+        self.bounding_box.min += off;
+        self.bounding_box.max += off;
         for v in &mut self.vertices {
             *v += off;
         }
     }
 }
 
-// Check that the two vectors are angled by less than Pi
-// ! This supposes that the polygon is defined counter-clockwise !
-fn inside(a: Vec3f, p1: Vec3f, p2: Vec3f) -> bool {
-    (p1 - a).cross(p2 - a).z > 0.
+// Check that the point lies on the inner side of the edge p1 -> p2.
+// The plane normal follows the vertices winding, so this holds for
+// any orientation, clockwise or counter-clockwise
+fn inside(a: Vec3f, p1: Vec3f, p2: Vec3f, normal: Vec3f) -> bool {
+    (p2 - p1).cross(a - p1).dot(normal) >= 0.
 }
 
 // Implementing the intersect and reflectance traits
@@ -85,6 +114,7 @@ impl Shape for ConvexPolygon {
                 intersect,
                 self.vertices[i],
                 self.vertices[(i + 1) % n_vertices],
+                self.plane_normal,
             ) {
                 return None;
             }
@@ -93,7 +123,12 @@ impl Shape for ConvexPolygon {
         Some(Intersection {
             point: intersect,
             normal: self.plane_normal,
-            reflectance: self.reflectance,
+            reflectance: self.reflectance.clone(),
+            barycentric: None,
+            uv: Some((
+                (intersect - self.uv_origin).dot(self.u_axis),
+                (intersect - self.uv_origin).dot(self.v_axis),
+            )),
         })
     }
 
@@ -101,3 +136,64 @@ impl Shape for ConvexPolygon {
         self.bounding_box.clone()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intersect_all_orientations() {
+        let axes = [
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        ];
+
+        for i in 0..3 {
+            for sign in &[1., -1.] {
+                // A square facing the origin along this axis
+                let n = axes[i].scaled(*sign);
+                let t1 = axes[(i + 1) % 3];
+                let t2 = axes[(i + 2) % 3];
+                let c = n.scaled(5.);
+                let mut vertices = vec![c - t1 - t2, c + t1 - t2, c + t1 + t2, c - t1 + t2];
+
+                for _ in 0..2 {
+                    let polygon =
+                        ConvexPolygon::create(vertices.clone(), Reflectance::create_default());
+
+                    let dir = (c + t1.scaled(0.5) - t2.scaled(0.3)).normalized();
+                    let hit = polygon.intersect(&Vec3f::zero(), &dir).unwrap();
+                    assert![(hit.point.dot(n) - 5.).abs() < 1e-6];
+                    assert![(hit.normal.dot(n).abs() - 1.).abs() < 1e-6];
+
+                    // Outside of the square, but on its plane
+                    let dir = (c + t1.scaled(1.5)).normalized();
+                    assert![polygon.intersect(&Vec3f::zero(), &dir).is_none()];
+
+                    // Texture coordinates span the square
+                    let (u, v) = hit.uv.unwrap();
+                    assert![(0. ..=1.).contains(&u) && (0. ..=1.).contains(&v)];
+                    let corner = polygon.intersect(&Vec3f::zero(), &vertices[2].normalized());
+                    let (u, v) = corner.unwrap().uv.unwrap();
+                    assert![(u - 1.).abs() < 1e-9 && (v - 1.).abs() < 1e-9];
+
+                    // Opposite winding
+                    vertices.reverse();
+                }
+            }
+        }
+    }
+}
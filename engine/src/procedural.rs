@@ -0,0 +1,368 @@
+use geometry::Vec3f;
+use texture::Texture;
+
+// Resolution independent textures, computed on the fly from the
+// texture coordinates or from the hit point
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    Uv,       // Texture coordinates, if the shape provides them
+    Position, // 3D position of the hit, solid texture
+}
+
+fn coordinates(mapping: Mapping, uv: Option<(f64, f64)>, point: &Vec3f, scale: f64) -> Vec3f {
+    match (mapping, uv) {
+        (Mapping::Uv, Some((u, v))) => Vec3f { x: u, y: v, z: 0. },
+        _ => *point,
+    }
+    .scaled(scale)
+}
+
+fn mix(a: Vec3f, b: Vec3f, t: f64) -> Vec3f {
+    a.scaled(1. - t) + b.scaled(t)
+}
+
+// ************************************************************
+// Checkerboard
+// ************************************************************
+
+#[derive(Clone, Debug)]
+pub struct Checker {
+    pub even: Vec3f,
+    pub odd: Vec3f,
+    pub scale: f64, // Number of squares per unit
+    pub mapping: Mapping,
+}
+
+impl Texture for Checker {
+    fn value(&self, uv: Option<(f64, f64)>, point: &Vec3f) -> Vec3f {
+        let p = coordinates(self.mapping, uv, point, self.scale);
+        let parity = (p.x.floor() + p.y.floor() + p.z.floor()) as i64;
+        if parity.rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+// ************************************************************
+// Perlin noise and turbulence
+// See https://mrl.nyu.edu/~perlin/noise/
+// ************************************************************
+
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    permutation: Vec<usize>, // 0..256, repeated twice to avoid wrapping the indices
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// Dot product in between the distance vector and one of 12 gradient directions
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+impl Perlin {
+    pub fn create(seed: u64) -> Perlin {
+        // Shuffle the table with a small linear congruential generator, reproducible
+        let mut state = seed;
+        let mut table: Vec<usize> = (0..256).collect();
+        for i in (1..256).rev() {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            let j = ((state >> 33) % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let mut permutation = table.clone();
+        permutation.extend(table);
+        Perlin { permutation }
+    }
+
+    // Smooth noise in [-1, 1], zero on the integer lattice
+    pub fn noise(&self, p: &Vec3f) -> f64 {
+        let (xf, yf, zf) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let xi = (xf as i64).rem_euclid(256) as usize;
+        let yi = (yf as i64).rem_euclid(256) as usize;
+        let zi = (zf as i64).rem_euclid(256) as usize;
+        let (x, y, z) = (p.x - xf, p.y - yf, p.z - zf);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let perm = &self.permutation;
+        let a = perm[xi] + yi;
+        let aa = perm[a] + zi;
+        let ab = perm[a + 1] + zi;
+        let b = perm[xi + 1] + yi;
+        let ba = perm[b] + zi;
+        let bb = perm[b + 1] + zi;
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(
+                    u,
+                    gradient(perm[aa], x, y, z),
+                    gradient(perm[ba], x - 1., y, z),
+                ),
+                lerp(
+                    u,
+                    gradient(perm[ab], x, y - 1., z),
+                    gradient(perm[bb], x - 1., y - 1., z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    gradient(perm[aa + 1], x, y, z - 1.),
+                    gradient(perm[ba + 1], x - 1., y, z - 1.),
+                ),
+                lerp(
+                    u,
+                    gradient(perm[ab + 1], x, y - 1., z - 1.),
+                    gradient(perm[bb + 1], x - 1., y - 1., z - 1.),
+                ),
+            ),
+        )
+        .clamp(-1., 1.)
+    }
+
+    // Sum of the absolute noise over octaves of decreasing amplitude, in [0, 2[
+    pub fn turbulence(&self, p: &Vec3f, octaves: usize) -> f64 {
+        let mut sum = 0.;
+        let mut frequency = 1.;
+        let mut amplitude = 1.;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(&p.scaled(frequency)).abs();
+            frequency *= 2.;
+            amplitude *= 0.5;
+        }
+        sum
+    }
+}
+
+// Plain noise, blending two colors
+#[derive(Clone, Debug)]
+pub struct Noise {
+    pub perlin: Perlin,
+    pub low: Vec3f,
+    pub high: Vec3f,
+    pub scale: f64,
+    pub mapping: Mapping,
+}
+
+impl Noise {
+    pub fn create(low: Vec3f, high: Vec3f, scale: f64, mapping: Mapping) -> Noise {
+        Noise {
+            perlin: Perlin::create(0),
+            low,
+            high,
+            scale,
+            mapping,
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, uv: Option<(f64, f64)>, point: &Vec3f) -> Vec3f {
+        let p = coordinates(self.mapping, uv, point, self.scale);
+        mix(self.low, self.high, 0.5 * (1. + self.perlin.noise(&p)))
+    }
+}
+
+// ************************************************************
+// Marble: sine veins, perturbed by turbulence
+// ************************************************************
+
+#[derive(Clone, Debug)]
+pub struct Marble {
+    pub perlin: Perlin,
+    pub base: Vec3f,
+    pub vein: Vec3f,
+    pub scale: f64,
+    pub turbulence: f64, // How twisted the veins are
+    pub octaves: usize,
+    pub mapping: Mapping,
+}
+
+impl Marble {
+    pub fn create(base: Vec3f, vein: Vec3f, scale: f64, mapping: Mapping) -> Marble {
+        Marble {
+            perlin: Perlin::create(1),
+            base,
+            vein,
+            scale,
+            turbulence: 5.,
+            octaves: 6,
+            mapping,
+        }
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, uv: Option<(f64, f64)>, point: &Vec3f) -> Vec3f {
+        let p = coordinates(self.mapping, uv, point, self.scale);
+        let phase = p.x + self.turbulence * self.perlin.turbulence(&p, self.octaves);
+        mix(self.vein, self.base, 0.5 * (1. + phase.sin()))
+    }
+}
+
+// ************************************************************
+// Wood: concentric rings around the y axis, perturbed by turbulence
+// ************************************************************
+
+#[derive(Clone, Debug)]
+pub struct Wood {
+    pub perlin: Perlin,
+    pub light: Vec3f,
+    pub dark: Vec3f,
+    pub scale: f64, // Rings per unit
+    pub turbulence: f64,
+    pub mapping: Mapping,
+}
+
+impl Wood {
+    pub fn create(light: Vec3f, dark: Vec3f, scale: f64, mapping: Mapping) -> Wood {
+        Wood {
+            perlin: Perlin::create(2),
+            light,
+            dark,
+            scale,
+            turbulence: 0.3,
+            mapping,
+        }
+    }
+}
+
+impl Texture for Wood {
+    fn value(&self, uv: Option<(f64, f64)>, point: &Vec3f) -> Vec3f {
+        let p = coordinates(self.mapping, uv, point, self.scale);
+        let radius = (p.x * p.x + p.z * p.z).sqrt();
+        let rings = radius + self.turbulence * self.perlin.turbulence(&p, 4);
+
+        // Sharp transition from the light to the dark part of each ring
+        let t = rings - rings.floor();
+        mix(self.light, self.dark, t * t * (3. - 2. * t))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn point(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    fn in_between(c: Vec3f, a: Vec3f, b: Vec3f) -> bool {
+        let inside = |c: f64, a: f64, b: f64| c >= a.min(b) - 1e-9 && c <= a.max(b) + 1e-9;
+        inside(c.x, a.x, b.x) && inside(c.y, a.y, b.y) && inside(c.z, a.z, b.z)
+    }
+
+    #[test]
+    fn test_checker() {
+        let checker = Checker {
+            even: Vec3f::ones(),
+            odd: Vec3f::zero(),
+            scale: 2.,
+            mapping: Mapping::Uv,
+        };
+
+        assert_eq![
+            checker.value(Some((0.1, 0.1)), &Vec3f::zero()),
+            Vec3f::ones()
+        ];
+        assert_eq![
+            checker.value(Some((0.6, 0.1)), &Vec3f::zero()),
+            Vec3f::zero()
+        ];
+        assert_eq![
+            checker.value(Some((0.6, 0.6)), &Vec3f::zero()),
+            Vec3f::ones()
+        ];
+
+        // No texture coordinates: falls back to the position, negative values included
+        assert_eq![checker.value(None, &point(-0.1, 0.1, 0.1)), Vec3f::zero()];
+        assert_eq![checker.value(None, &point(-0.1, -0.1, 0.1)), Vec3f::ones()];
+    }
+
+    #[test]
+    fn test_perlin() {
+        let perlin = Perlin::create(42);
+
+        // Zero on the lattice, bounded and continuous elsewhere
+        assert_eq![perlin.noise(&point(3., -2., 7.)), 0.];
+
+        let mut min = 1.;
+        let mut max = -1.;
+        for i in 0..1000 {
+            let p = point(i as f64 * 0.137, i as f64 * 0.071 - 20., i as f64 * 0.013);
+            let n = perlin.noise(&p);
+            assert![(-1. ..=1.).contains(&n)];
+            assert![(n - perlin.noise(&(p + Vec3f::ones().scaled(1e-6)))).abs() < 1e-4];
+            min = f64::min(min, n);
+            max = f64::max(max, n);
+
+            let t = perlin.turbulence(&p, 5);
+            assert![(0. ..2.).contains(&t)];
+        }
+
+        // Not flat
+        assert![max - min > 0.5];
+
+        // Reproducible
+        let p = point(0.3, 0.6, 0.9);
+        assert_eq![Perlin::create(42).noise(&p), perlin.noise(&p)];
+        assert![Perlin::create(7).noise(&p) != perlin.noise(&p)];
+    }
+
+    #[test]
+    fn test_color_ranges() {
+        let (a, b) = (point(0.9, 0.9, 0.8), point(0.2, 0.1, 0.1));
+        let textures: Vec<Box<dyn Texture>> = vec![
+            Box::new(Noise::create(a, b, 3., Mapping::Position)),
+            Box::new(Marble::create(a, b, 2., Mapping::Position)),
+            Box::new(Wood::create(a, b, 4., Mapping::Uv)),
+        ];
+
+        for texture in &textures {
+            for i in 0..200 {
+                let p = point(i as f64 * 0.31, i as f64 * 0.17, -(i as f64) * 0.05);
+                let uv = Some((i as f64 * 0.01, 1. - i as f64 * 0.003));
+                assert![in_between(texture.value(uv, &p), a, b)];
+                assert![in_between(texture.value(None, &p), a, b)];
+            }
+        }
+    }
+
+    #[test]
+    fn test_wood_rings() {
+        let wood = Wood {
+            turbulence: 0.,
+            ..Wood::create(Vec3f::ones(), Vec3f::zero(), 1., Mapping::Position)
+        };
+
+        // Same radius, same color. Ring boundaries are light
+        let p = point(0.3, 5., 0.4);
+        assert_eq![wood.value(None, &p), wood.value(None, &point(0.5, -2., 0.))];
+        assert_eq![wood.value(None, &point(2., 0., 0.)), Vec3f::ones()];
+    }
+}
@@ -1,110 +1,252 @@
 extern crate rayon;
 use renderer::rayon::prelude::*;
 
+use bvh::Bvh;
+use camera::Camera;
+use filter::Filter;
 use framebuffer::FrameBuffer;
 use geometry::Vec3f;
-use lights::Light;
-use optics::reflect;
-use optics::reflect_ray;
-use optics::refract_ray;
+use integrator::Integrator;
+use integrator::Whitted;
+use rig::Rig;
+use sampling::Rng;
+use sampling::SamplePattern;
 use scene::Scene;
-use shapes::find_closest_intersect;
-use shapes::intersect_shape_set;
-use shapes::Intersection;
-use shapes::Shape;
+use spectrum;
 use std::time::Instant;
 
 pub struct Renderer {
-    pub fov: f64,
-    pub half_fov: f64,
     pub height: f64,
     pub width: f64,
-    pub ratio: f64,
+    pub integrator: Box<dyn Integrator>,
+    pub samples_per_pixel: usize,
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,
+
+    // Adaptive sampling: patches keep getting batches of samples_per_pixel samples
+    // while their estimated error is above the threshold, up to max_samples_per_pixel
+    pub adaptive_threshold: Option<f64>,
+    pub max_samples_per_pixel: usize,
+
+    // Side of the square patches distributed over the threads, the last ones
+    // on the right and bottom edges are cropped to the picture
+    pub patch_size: usize,
+
+    // Positions on the lens averaged in every pixel sample, when the camera has an aperture
+    pub lens_samples: usize,
+
+    // Views rendered in the picture, stereo pairs or cube maps
+    pub rig: Rig,
+}
+
+// Running statistics of the samples of a single pixel
+struct PixelSamples {
+    rng: Rng,
+    scramble: (u32, u32), // Of the sample pattern, the same for all the batches
+    taken: usize,         // Samples drawn, including the ones outside of the filter support
+    color: Vec3f,         // Weighted by the reconstruction filter
+    total_weight: f64,
+    count: usize,
+    mean: f64, // Luminance, Welford's online algorithm
+    m2: f64,
+}
+
+fn luminance(color: &Vec3f) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
 }
 
-pub fn create_renderer(fov: f64, height: f64, width: f64) -> Renderer {
+impl PixelSamples {
+    fn create(rng: Rng) -> PixelSamples {
+        PixelSamples {
+            rng,
+            scramble: (0, 0),
+            taken: 0,
+            color: Vec3f::zero(),
+            total_weight: 0.,
+            count: 0,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+
+    fn add(&mut self, color: Vec3f, weight: f64) {
+        self.color += color.scaled(weight);
+        self.total_weight += weight;
+
+        self.count += 1;
+        let value = luminance(&color);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    // Relative standard error of the mean luminance,
+    // with a small floor so that the dark pixels can converge too
+    fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        (self.variance() / self.count as f64).sqrt() / (self.mean.abs() + 1e-2)
+    }
+
+    // Spectral samples are in CIE XYZ, converted to RGB
+    fn value(&self, spectral: bool) -> Vec3f {
+        if self.total_weight.abs() < 1e-12 {
+            return Vec3f::zero();
+        }
+
+        // Negative lobes (Mitchell) and colors out of the RGB gamut can undershoot
+        let mut color = self.color.scaled(1. / self.total_weight);
+        if spectral {
+            color = spectrum::xyz_to_rgb(color);
+        }
+        Vec3f {
+            x: color.x.max(0.),
+            y: color.y.max(0.),
+            z: color.z.max(0.),
+        }
+    }
+}
+
+// The field of view and the aspect ratio are set on the scene camera
+pub fn create_renderer(height: f64, width: f64) -> Renderer {
     Renderer {
-        fov,
-        half_fov: (fov / 2.).tan(),
-        height: height,
-        width: width,
-        ratio: width / height,
+        height,
+        width,
+        integrator: Box::new(Whitted::create_default()),
+        samples_per_pixel: 1,
+        sample_pattern: SamplePattern::Grid,
+        filter: Filter::Box,
+        adaptive_threshold: None,
+        max_samples_per_pixel: 256,
+        patch_size: 32,
+        lens_samples: 1,
+        rig: Rig::Mono,
     }
 }
 
 impl Renderer {
     pub fn render(&self, frame: &mut FrameBuffer, scene: &Scene) -> String {
-        let orig = &scene.camera;
+        self.render_with_sample_counts(frame, scene, None)
+    }
+
+    // Same as render, also reporting how many samples each pixel took, normalized by
+    // max_samples_per_pixel. Mostly useful with adaptive sampling
+    pub fn render_with_sample_counts(
+        &self,
+        frame: &mut FrameBuffer,
+        scene: &Scene,
+        mut sample_counts: Option<&mut FrameBuffer>,
+    ) -> String {
         let now = Instant::now();
 
-        let background = Vec3f {
-            x: 0.1,
-            y: 0.1,
-            z: 0.1,
-        };
+        // Acceleration structure over the scene shapes, rebuilt every frame
+        // since the shapes can be freely modified in between two renders
+        let bvh = Bvh::create_from_shapes(&scene.shapes);
+        let cameras = self.rig.cameras(&scene.camera);
 
         // Distribute the computation over spatially coherent patches
-        let patch_size = 32;
-
-        if (frame.height % patch_size != 0) || (frame.width % patch_size != 0) {
-            println!("Dimensions mismatch")
-        }
+        let patch_size = self.patch_size.max(1);
+        let (frame_width, frame_height) = (frame.width, frame.height);
 
-        let n_height = frame.height / patch_size;
-        let n_width = frame.width / patch_size;
+        let n_height = frame.height.div_ceil(patch_size);
+        let n_width = frame.width.div_ceil(patch_size);
         let n_patches = n_height * n_width;
 
+        // Pixel ranges covered by a patch, lines then columns
+        let patch_bounds = |p: usize| {
+            let line = p / n_width * patch_size;
+            let col = p % n_width * patch_size;
+            (
+                line..(line + patch_size).min(frame_height),
+                col..(col + patch_size).min(frame_width),
+            )
+        };
+
         println!(
             "Rendering using patches of size {}, using {} patches overall",
             patch_size, n_patches
         );
 
         // Render, distribute the patches over threads
-        let render_queue: Vec<Vec<Vec3f>> = (0..n_patches)
+        let render_queue: Vec<Vec<PixelSamples>> = (0..n_patches)
             .into_par_iter()
             .map(|p| {
-                // Pre-allocate the patch
-                let mut buffer: Vec<Vec3f> = Vec::with_capacity(patch_size * patch_size);
-
-                let p_line = p % n_width * patch_size;
-                let p_col = p / n_width * patch_size;
-                let p_line_end = p_line + patch_size;
-                let p_col_end = p_col + patch_size;
+                let (lines, cols) = patch_bounds(p);
+
+                // Seeded per pixel, the result does not depend on the thread scheduling
+                // nor on the patch size
+                let mut pixels: Vec<PixelSamples> = lines
+                    .clone()
+                    .flat_map(|i| {
+                        cols.clone().map(move |j| {
+                            PixelSamples::create(Rng::create((i * frame_width + j) as u64))
+                        })
+                    })
+                    .collect();
 
                 // Backproject locally, keep spatial coherency
-                for i in p_col..p_col_end {
-                    for j in p_line..p_line_end {
-                        buffer.push(cast_ray(
-                            &orig,
-                            self.backproject(j, i),
-                            &scene.shapes,
-                            &scene.lights,
-                            &background,
-                            1,
-                        ));
+                let sample_patch = |pixels: &mut [PixelSamples], n_samples: usize| {
+                    let mut k = 0;
+                    for i in lines.clone() {
+                        for j in cols.clone() {
+                            self.sample_pixel(
+                                &mut pixels[k],
+                                j,
+                                i,
+                                n_samples,
+                                &cameras,
+                                scene,
+                                &bvh,
+                            );
+                            k += 1;
+                        }
+                    }
+                };
+
+                let batch = self.samples_per_pixel.max(1);
+                sample_patch(&mut pixels, batch);
+
+                // Refine the noisy patches, the error is averaged over the patch
+                if let Some(threshold) = self.adaptive_threshold {
+                    loop {
+                        let n_taken = pixels[0].taken;
+                        let error =
+                            pixels.iter().map(|p| p.error()).sum::<f64>() / pixels.len() as f64;
+                        if error <= threshold || n_taken >= self.max_samples_per_pixel {
+                            break;
+                        }
+                        sample_patch(&mut pixels, batch.min(self.max_samples_per_pixel - n_taken));
                     }
                 }
-                buffer
+                pixels
             })
             .collect();
 
         // Reconstruct the picture in the framebuffer
-        let mut p_width = 0;
-        let mut p_height;
-
+        let spectral = self.integrator.is_spectral();
         for (p, render_patch) in render_queue.iter().enumerate() {
-            p_height = (p / n_width) * patch_size;
-            let p_height_end = p_height + patch_size;
-            let p_width_end = p_width + patch_size;
+            let (lines, cols) = patch_bounds(p);
 
             let mut k = 0;
-            for j in p_height..p_height_end {
-                for i in p_width..p_width_end {
-                    frame.buffer[j][i] = render_patch[k];
+            for j in lines {
+                for i in cols.clone() {
+                    frame.buffer[j][i] = render_patch[k].value(spectral);
+                    if let Some(ref mut counts) = sample_counts {
+                        counts.buffer[j][i] = Vec3f::ones().scaled(
+                            render_patch[k].taken as f64 / self.max_samples_per_pixel.max(1) as f64,
+                        );
+                    }
                     k += 1;
                 }
             }
-            p_width = p_width_end % frame.width;
         }
 
         // Output some metrics
@@ -122,187 +264,397 @@ impl Renderer {
 
         println!("{}", message);
         println!("{} threads used", rayon::current_num_threads());
-        return message;
+        message
     }
 
-    fn backproject(&self, i: usize, j: usize) -> Vec3f {
-        Vec3f {
-            x: 2. * (i as f64 / self.width - 0.5) * self.half_fov * self.ratio,
-            y: -2. * (j as f64 / self.height - 0.5) * self.half_fov,
-            z: -1.,
+    // Add n samples to a pixel
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(
+        &self,
+        pixel: &mut PixelSamples,
+        i: usize,
+        j: usize,
+        n_samples: usize,
+        cameras: &[Camera],
+        scene: &Scene,
+        bvh: &Bvh,
+    ) {
+        // Spread the samples over the filter support around the pixel center,
+        // and reconstruct with the normalized filter weights
+        let radius = self.filter.radius();
+
+        // Further batches of adaptive sampling carry on with the sample pattern
+        let first = pixel.taken;
+        if first == 0 {
+            pixel.scramble = self.sample_pattern.scramble(&mut pixel.rng);
         }
-        .normalized()
-    }
-}
+        pixel.taken += n_samples;
 
-fn diffusion_factor(intersection: &Intersection, light_dir: &Vec3f) -> f64 {
-    light_dir.dot(intersection.normal).max(0.)
-}
+        // The view is picked from the pixel center, its samples all go through the same camera
+        let view = self.rig.view_at(
+            (i as f64 + 0.5) / self.width,
+            (j as f64 + 0.5) / self.height,
+        );
+        let camera = &cameras[view];
+
+        let samples =
+            self.sample_pattern
+                .samples_from(first, n_samples, pixel.scramble, &mut pixel.rng);
+        for (u, v) in samples {
+            let dx = (2. * u - 1.) * radius;
+            let dy = (2. * v - 1.) * radius;
+            let weight = self.filter.weight(dx, dy);
+            if weight == 0. {
+                continue;
+            }
 
-fn specular_factor(intersection: &Intersection, origin: &Vec3f, light_dir: &Vec3f) -> f64 {
-    // Compute the light reflected vector at that point
-    let incident = -*light_dir;
-    let reflected = reflect(incident, intersection.normal);
+            let (x, y) = self.rig.to_view(
+                view,
+                (i as f64 + 0.5 + dx) / self.width,
+                (j as f64 + 0.5 + dy) / self.height,
+            );
 
-    // The specular reflection coeff is the dot product in between the purely
-    // reflected ray and the viewerś point of view
-    let dir_to_viewer = (*origin - intersection.point).normalized();
-    reflected.dot(dir_to_viewer).max(0.)
+            // Thin lens, stratified over the lens. A single ray otherwise
+            let lens_positions = if camera.aperture > 0. {
+                SamplePattern::Jitter.samples(self.lens_samples.max(1), &mut pixel.rng)
+            } else {
+                vec![(0.5, 0.5)]
+            };
+
+            // Nothing to see outside of the projection (fisheye circle)
+            let mut color = Vec3f::zero();
+            for (u1, u2) in lens_positions.iter() {
+                if let Some((orig, dir)) = camera.ray(x, y, *u1, *u2) {
+                    color += self
+                        .integrator
+                        .radiance(&orig, dir, scene, bvh, &mut pixel.rng);
+                }
+            }
+            pixel.add(color.scaled(1. / lens_positions.len() as f64), weight);
+        }
+    }
 }
 
-fn direct_lighting(
-    origin: &Vec3f,
-    intersection: &Intersection,
-    shapes: &[Box<dyn Shape + Sync>],
-    lights: &[Light],
-) -> Vec3f {
-    // Compute the lighting contribution of direct illumination,
-    // meaning diffuse and specular lighting
-
-    let mut light_intensity = Vec3f::zero();
-    let mut intersect_orig: Vec3f;
-
-    for light in lights {
-        let light_dir = (light.position - intersection.point).normalized();
-
-        intersect_orig = if light_dir.dot(intersection.normal) < 0. {
-            intersection.point - intersection.normal.scaled(1e-3)
-        } else {
-            intersection.point + intersection.normal.scaled(1e-3)
+#[cfg(test)]
+mod test {
+    use super::*;
+    use framebuffer::create_frame_buffer;
+    use integrator;
+    use lights::create_light;
+    use polygon::ConvexPolygon;
+    use shapes::Reflectance;
+    use sphere;
+
+    #[test]
+    fn test_antialiasing() {
+        // A white triangle against the black background, with a slanted edge
+        let mut scene = Scene::new();
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                Vec3f {
+                    x: -10.,
+                    y: -10.,
+                    z: -5.,
+                },
+                Vec3f {
+                    x: 3.,
+                    y: -10.,
+                    z: -5.,
+                },
+                Vec3f {
+                    x: -10.,
+                    y: 10.,
+                    z: -5.,
+                },
+            ],
+            Reflectance::create_default(),
+        )));
+
+        let render = |samples_per_pixel: usize, pattern: SamplePattern, filter: Filter| {
+            let mut renderer = create_renderer(32., 32.);
+            renderer.integrator = integrator::create("albedo").unwrap();
+            renderer.samples_per_pixel = samples_per_pixel;
+            renderer.sample_pattern = pattern;
+            renderer.filter = filter;
+            let mut frame = create_frame_buffer(32, 32);
+            renderer.render(&mut frame, &scene);
+            frame
+        };
+        let n_partial = |frame: &FrameBuffer| {
+            frame
+                .buffer
+                .iter()
+                .flat_map(|line| line.iter())
+                .filter(|p| p.x > 0.01 && p.x < 0.99)
+                .count()
         };
 
-        if intersect_shape_set(&intersect_orig, &light_dir, &shapes[..]) {
-            // Cast shadow, this light is not visible from this point of view
-            continue;
+        // One sample per pixel, hard edge
+        let aliased = render(1, SamplePattern::Grid, Filter::Box);
+        assert_eq![n_partial(&aliased), 0];
+        assert_eq![aliased.buffer[31][0], Vec3f::ones()];
+        assert_eq![aliased.buffer[0][31], Vec3f::zero()];
+
+        // Partially covered pixels along the edge, with every pattern and filter
+        for pattern in [
+            SamplePattern::Jitter,
+            SamplePattern::Halton,
+            SamplePattern::Sobol,
+        ]
+        .iter()
+        {
+            for filter in [
+                Filter::Box,
+                Filter::Tent,
+                Filter::Gaussian,
+                Filter::Mitchell,
+            ]
+            .iter()
+            {
+                let smooth = render(16, *pattern, *filter);
+                assert![n_partial(&smooth) > 16];
+                assert![(smooth.buffer[31][0] - Vec3f::ones()).norm() < 1e-9];
+                assert_eq![smooth.buffer[0][31], Vec3f::zero()];
+            }
         }
-
-        // Handle diffuse lighting
-        let diffusion = diffusion_factor(&intersection, &light_dir);
-        light_intensity += (light.color * intersection.reflectance.diffuse_color)
-            .scaled(diffusion)
-            .scaled(light.intensity);
-
-        // Handle specular reflections
-        let specular = (specular_factor(&intersection, &origin, &light_dir)
-            * intersection.reflectance.specular)
-            .powf(intersection.reflectance.specular_exponent);
-        light_intensity += light.color.scaled(specular);
     }
 
-    light_intensity.scaled(intersection.reflectance.diffusion)
-}
+    #[test]
+    fn test_adaptive_sampling() {
+        // A sphere in the left patch, nothing in the right one
+        let mut scene = Scene::new();
+        scene.shapes.push(Box::new(sphere::create(
+            Vec3f {
+                x: -2.,
+                y: 0.,
+                z: -4.,
+            },
+            1.5,
+            Reflectance::create_default(),
+        )));
+        scene.lights.push(create_light(
+            Vec3f {
+                x: 0.,
+                y: 5.,
+                z: 0.,
+            },
+            Vec3f::ones(),
+            1.,
+        ));
+
+        scene.camera.aspect = 2.;
+        let mut renderer = create_renderer(32., 64.);
+        renderer.integrator = integrator::create("path").unwrap();
+        renderer.samples_per_pixel = 4;
+        renderer.sample_pattern = SamplePattern::Jitter;
+        renderer.adaptive_threshold = Some(1e-3);
+        renderer.max_samples_per_pixel = 30;
+
+        let mut frame = create_frame_buffer(64, 32);
+        let mut counts = create_frame_buffer(64, 32);
+        renderer.render_with_sample_counts(&mut frame, &scene, Some(&mut counts));
+
+        // Noisy patch refined up to the cap, empty patch left after the first batch
+        assert_eq![counts.buffer[16][16], Vec3f::ones()];
+        assert_eq![counts.buffer[16][48], Vec3f::ones().scaled(4. / 30.)];
+        assert![frame.buffer[16][16].x > 0.];
+        assert_eq![frame.buffer[16][48], Vec3f::zero()];
+
+        // A loose threshold stops earlier
+        renderer.adaptive_threshold = Some(10.);
+        renderer.render_with_sample_counts(&mut frame, &scene, Some(&mut counts));
+        assert![counts.buffer[16][16].x < 1.];
+    }
 
-fn reflected_lighting(
-    incident: Vec3f,
-    intersection: &Intersection,
-    shapes: &[Box<dyn Shape + Sync>],
-    lights: &[Light],
-    background: &Vec3f,
-    n_recursion: u8,
-) -> Vec3f {
-    // We may or may not have a reflected ray, angle dependent
-    let reflect = reflect_ray(
-        incident,
-        &intersection,
-        intersection.reflectance.refractive_index,
-    );
-
-    match reflect {
-        Some(reflection) => cast_ray(
-            &reflection.0,
-            reflection.1,
-            shapes,
-            lights,
-            background,
-            n_recursion + 1,
-        )
-        .scaled(intersection.reflectance.reflection),
-        _ => Vec3f::zero(),
+    #[test]
+    fn test_adaptive_edges() {
+        // Slanted edge of a lit triangle, Whitted and a regular grid
+        let mut scene = Scene::new();
+        scene.shapes.push(Box::new(ConvexPolygon::create(
+            vec![
+                Vec3f {
+                    x: -10.,
+                    y: -10.,
+                    z: -5.,
+                },
+                Vec3f {
+                    x: 3.,
+                    y: -10.,
+                    z: -5.,
+                },
+                Vec3f {
+                    x: -10.,
+                    y: 10.,
+                    z: -5.,
+                },
+            ],
+            Reflectance::create_default(),
+        )));
+        scene.lights.push(create_light(
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: 5.,
+            },
+            Vec3f::ones(),
+            1.,
+        ));
+
+        let mut renderer = create_renderer(32., 32.);
+        renderer.adaptive_threshold = Some(0.01);
+        renderer.max_samples_per_pixel = 64;
+        renderer.patch_size = 8;
+
+        let mut frame = create_frame_buffer(32, 32);
+        let mut counts = create_frame_buffer(32, 32);
+        renderer.render_with_sample_counts(&mut frame, &scene, Some(&mut counts));
+
+        // The second batch lands elsewhere in the pixels: the edge gets refined,
+        // the flat background and the inside of the triangle do not
+        let edge = counts.buffer[28][12].x;
+        let flat = Vec3f::ones().scaled(2. / 64.);
+        assert_eq![counts.buffer[0][31], flat];
+        assert_eq![counts.buffer[31][0], flat];
+        assert![edge > 2. * flat.x];
     }
-}
 
-// Compute the lighting contribution of a refracted ray
-fn refracted_lighting(
-    incident: Vec3f,
-    intersection: &Intersection,
-    shapes: &[Box<dyn Shape + Sync>],
-    lights: &[Light],
-    background: &Vec3f,
-    n_recursion: u8,
-) -> Vec3f {
-    // We may or may not have a reflected ray, angle dependent
-    let refract = refract_ray(
-        incident,
-        intersection,
-        intersection.reflectance.refractive_index,
-    );
-
-    match refract {
-        Some(refracted_ray) => cast_ray(
-            &refracted_ray.0,
-            refracted_ray.1,
-            shapes,
-            lights,
-            background,
-            n_recursion + 1,
-        )
-        .scaled(1. - intersection.reflectance.reflection),
-        _ => Vec3f::zero(),
+    #[test]
+    fn test_depth_of_field() {
+        let mut scene = Scene::new();
+        scene.shapes.push(Box::new(sphere::create(
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -4.,
+            },
+            1.,
+            Reflectance::create_default(),
+        )));
+
+        let mut renderer = create_renderer(32., 32.);
+        renderer.integrator = integrator::create("albedo").unwrap();
+        renderer.lens_samples = 16;
+
+        // Pixels neither on the sphere nor on the background
+        let blurred_pixels = |scene: &Scene| {
+            let mut frame = create_frame_buffer(32, 32);
+            renderer.render(&mut frame, scene);
+            let peak = frame.buffer[16][16].x;
+            assert![peak > 0.];
+            frame
+                .buffer
+                .iter()
+                .flat_map(|line| line.iter())
+                .filter(|c| c.x > 1e-3 * peak && c.x < 0.999 * peak)
+                .count()
+        };
+
+        // Pinhole, everything is sharp
+        assert_eq![blurred_pixels(&scene), 0];
+
+        // In focus, only the silhouette gets a little soft
+        scene.camera.aperture = 0.2;
+        scene.camera.focus_distance = 3.2;
+        let in_focus = blurred_pixels(&scene);
+
+        // Focused far behind, a wide blurry halo. Polygonal bokeh works too
+        scene.camera.focus_distance = 30.;
+        let out_of_focus = blurred_pixels(&scene);
+        assert![out_of_focus > 2 * in_focus];
+        scene.camera.blades = 6;
+        assert![blurred_pixels(&scene) > 2 * in_focus];
     }
-}
 
-fn cast_ray(
-    orig: &Vec3f,
-    dir: Vec3f,
-    shapes: &[Box<dyn Shape + Sync>],
-    lights: &[Light],
-    background: &Vec3f,
-    n_recursion: u8,
-) -> Vec3f {
-    if n_recursion > 3 {
-        return *background;
+    #[test]
+    fn test_stereo_rig() {
+        let mut scene = Scene::create_default();
+        scene.camera.aspect = 2.;
+        let mut renderer = create_renderer(24., 48.);
+        renderer.integrator = integrator::create("normals").unwrap();
+        renderer.rig = Rig::SideBySide { interocular: 0.5 };
+        renderer.patch_size = 7;
+
+        let mut frame = create_frame_buffer(48, 24);
+        renderer.render(&mut frame, &scene);
+
+        // Each half is the picture seen by one eye
+        let eyes = renderer.rig.cameras(&scene.camera);
+        let mut mono = create_renderer(24., 24.);
+        mono.integrator = integrator::create("normals").unwrap();
+
+        for (e, eye) in eyes.iter().enumerate() {
+            scene.camera = eye.clone();
+            let mut eye_frame = create_frame_buffer(24, 24);
+            mono.render(&mut eye_frame, &scene);
+
+            for j in 0..24 {
+                for i in 0..24 {
+                    let diff = frame.buffer[j][i + 24 * e] - eye_frame.buffer[j][i];
+                    assert![diff.norm() < 1e-6];
+                }
+            }
+        }
+
+        // With some parallax in between
+        assert![frame.buffer[..24] != frame.buffer[24..]];
+        assert![(0..24).any(|j| frame.buffer[j][..24] != frame.buffer[j][24..])];
     }
 
-    let result = find_closest_intersect(orig, dir, shapes);
-
-    match result {
-        Some(intersect_result) => {
-            let intersection = &intersect_result.0;
-
-            let mut light_intensity = *background;
-
-            // Go through all the lights, sum up the individual contributions
-            light_intensity += direct_lighting(orig, &intersection, &shapes[..], &lights[..]);
-
-            if intersection.reflectance.is_glass_like {
-                // Compute the reflections recursively
-                light_intensity += reflected_lighting(
-                    dir,
-                    &intersection,
-                    &shapes[..],
-                    &lights[..],
-                    background,
-                    n_recursion,
-                );
-
-                // Compute the refracted light recusively
-                light_intensity += refracted_lighting(
-                    dir,
-                    &intersection,
-                    &shapes[..],
-                    &lights[..],
-                    background,
-                    n_recursion,
-                );
+    #[test]
+    fn test_arbitrary_resolution() {
+        let mut scene = Scene::create_default();
+        let (width, height) = (641, 479);
+        scene.camera.aspect = width as f64 / height as f64;
+
+        let render = |patch_size: usize| {
+            let mut renderer = create_renderer(height as f64, width as f64);
+            renderer.integrator = integrator::create("normals").unwrap();
+            renderer.patch_size = patch_size;
+
+            // Sentinel value, which no integrator can output
+            let mut frame = create_frame_buffer(width, height);
+            let mut counts = create_frame_buffer(width, height);
+            for line in frame.buffer.iter_mut().chain(counts.buffer.iter_mut()) {
+                for pixel in line.iter_mut() {
+                    *pixel = Vec3f::ones().scaled(-1.);
+                }
             }
-            light_intensity
+            renderer.render_with_sample_counts(&mut frame, &scene, Some(&mut counts));
+            (frame, counts)
+        };
+
+        let (frame, counts) = render(32);
+        assert_eq![frame.buffer.len(), height];
+        for (line, count_line) in frame.buffer.iter().zip(counts.buffer.iter()) {
+            assert_eq![line.len(), width];
+            assert![line.iter().all(|p| p.min() >= 0.)];
+            assert![count_line.iter().all(|c| c.min() > 0.)];
         }
-        // No intersection, do nothing and test the next shape
-        _ => {
-            if n_recursion > 1 {
-                *background
-            } else {
-                Vec3f::zero()
+
+        // The geometry lands on the same pixels whatever the patches, edge ones included
+        for patch_size in [7, 13, 64, 1000].iter() {
+            assert![render(*patch_size).0.buffer == frame.buffer];
+        }
+    }
+
+    #[test]
+    fn test_stochastic_render() {
+        let scene = Scene::create_default();
+        let mut renderer = create_renderer(32., 32.);
+        renderer.integrator = integrator::create("path").unwrap();
+        renderer.samples_per_pixel = 4;
+
+        let mut first = create_frame_buffer(32, 32);
+        let mut second = create_frame_buffer(32, 32);
+        renderer.render(&mut first, &scene);
+        renderer.render(&mut second, &scene);
+
+        // Reproducible, regardless of the threads
+        for (line_a, line_b) in first.buffer.iter().zip(second.buffer.iter()) {
+            for (a, b) in line_a.iter().zip(line_b.iter()) {
+                assert![a.x.is_finite() && a.y.is_finite() && a.z.is_finite()];
+                assert![a.min() >= 0.];
+                assert_eq![a, b];
             }
         }
     }
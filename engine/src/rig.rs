@@ -0,0 +1,182 @@
+use camera::Camera;
+use geometry::Vec3f;
+use std::f64::consts::PI;
+
+// Several views rendered side by side in a single picture, from the scene camera.
+// The picture is split in a grid of views, each with its own camera
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rig {
+    Mono,
+    SideBySide { interocular: f64 }, // Stereo, left eye on the left
+    OverUnder { interocular: f64 },  // Stereo, left eye on top
+    CubeMap, // Six 90 degrees faces: +x, -x, +y on top, -y, +z, -z below. Use a 3:2 picture
+}
+
+pub const NAMES: [&str; 4] = ["mono", "side-by-side", "over-under", "cube-map"];
+
+// Rig from its name, the interocular distance only matters for the stereo ones
+pub fn create(name: &str, interocular: f64) -> Option<Rig> {
+    match name {
+        "mono" => Some(Rig::Mono),
+        "side-by-side" => Some(Rig::SideBySide { interocular }),
+        "over-under" => Some(Rig::OverUnder { interocular }),
+        "cube-map" => Some(Rig::CubeMap),
+        _ => None,
+    }
+}
+
+fn vec(x: f64, y: f64, z: f64) -> Vec3f {
+    Vec3f { x, y, z }
+}
+
+// Eye looking in the same direction as the camera, parallel axes
+fn eye(camera: &Camera, shift: f64, aspect: f64) -> Camera {
+    let (right, _, _) = camera.basis();
+    let mut eye = camera.clone();
+    eye.offset(right.scaled(shift));
+    eye.aspect = aspect;
+    eye
+}
+
+impl Rig {
+    // Columns and lines of views in the picture
+    pub fn layout(&self) -> (usize, usize) {
+        match self {
+            Rig::Mono => (1, 1),
+            Rig::SideBySide { .. } => (2, 1),
+            Rig::OverUnder { .. } => (1, 2),
+            Rig::CubeMap => (3, 2),
+        }
+    }
+
+    // One camera per view, lines then columns. The scene camera aspect ratio
+    // is the one of the whole picture
+    pub fn cameras(&self, camera: &Camera) -> Vec<Camera> {
+        let (columns, lines) = self.layout();
+        let aspect = camera.aspect * lines as f64 / columns as f64;
+
+        match self {
+            Rig::Mono => vec![camera.clone()],
+            Rig::SideBySide { interocular } | Rig::OverUnder { interocular } => vec![
+                eye(camera, -interocular / 2., aspect),
+                eye(camera, interocular / 2., aspect),
+            ],
+            Rig::CubeMap => {
+                // Axis aligned, from the camera position. Side faces upright
+                let faces = [
+                    (vec(1., 0., 0.), vec(0., 1., 0.)),
+                    (vec(-1., 0., 0.), vec(0., 1., 0.)),
+                    (vec(0., 1., 0.), vec(0., 0., 1.)),
+                    (vec(0., -1., 0.), vec(0., 0., -1.)),
+                    (vec(0., 0., 1.), vec(0., 1., 0.)),
+                    (vec(0., 0., -1.), vec(0., 1., 0.)),
+                ];
+                faces
+                    .iter()
+                    .map(|(forward, up)| {
+                        Camera::create(
+                            camera.position,
+                            camera.position + *forward,
+                            *up,
+                            PI / 2.,
+                            1.,
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // View covering a point of the picture, (0, 0) on the top left corner
+    pub fn view_at(&self, x: f64, y: f64) -> usize {
+        let (columns, lines) = self.layout();
+        let column = ((x * columns as f64) as usize).min(columns - 1);
+        let line = ((y * lines as f64) as usize).min(lines - 1);
+        line * columns + column
+    }
+
+    // Picture coordinates to the coordinates within a view. Can go a little past [0, 1]
+    // around the edges of the view, with the reconstruction filter
+    pub fn to_view(&self, view: usize, x: f64, y: f64) -> (f64, f64) {
+        let (columns, lines) = self.layout();
+        let (column, line) = (view % columns, view / columns);
+        (
+            x * columns as f64 - column as f64,
+            y * lines as f64 - line as f64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use camera::Projection;
+
+    fn close(a: Vec3f, b: Vec3f) -> bool {
+        (a - b).norm() < 1e-9
+    }
+
+    #[test]
+    fn test_stereo() {
+        let mut camera = Camera::create_default();
+        camera.aspect = 2.;
+
+        let rig = create("side-by-side", 0.1).unwrap();
+        let eyes = rig.cameras(&camera);
+        assert_eq![eyes.len(), 2];
+        assert![close(eyes[0].position, vec(-0.05, 0., 0.))];
+        assert![close(eyes[1].position, vec(0.05, 0., 0.))];
+        assert_eq![eyes[0].aspect, 1.];
+        assert![close(eyes[1].direction(0.5, 0.5), vec(0., 0., -1.))];
+
+        assert_eq![rig.view_at(0.2, 0.9), 0];
+        assert_eq![rig.view_at(0.7, 0.1), 1];
+        assert_eq![rig.to_view(1, 0.75, 0.3), (0.5, 0.3)];
+
+        let rig = create("over-under", 0.1).unwrap();
+        assert_eq![rig.cameras(&camera)[0].aspect, 4.];
+        assert_eq![rig.view_at(0.7, 0.1), 0];
+        assert_eq![rig.view_at(0.2, 0.9), 1];
+        assert_eq![rig.to_view(1, 0.3, 0.75), (0.3, 0.5)];
+
+        assert![create("quad", 0.1).is_none()];
+    }
+
+    #[test]
+    fn test_cube_map() {
+        let mut camera = Camera::create_default();
+        camera.offset(vec(1., 2., 3.));
+        camera.projection = Projection::Fisheye;
+
+        let rig = Rig::CubeMap;
+        let faces = rig.cameras(&camera);
+        assert_eq![faces.len(), 6];
+
+        let axes = [
+            vec(1., 0., 0.),
+            vec(-1., 0., 0.),
+            vec(0., 1., 0.),
+            vec(0., -1., 0.),
+            vec(0., 0., 1.),
+            vec(0., 0., -1.),
+        ];
+        for (face, axis) in faces.iter().zip(axes.iter()) {
+            assert_eq![face.position, camera.position];
+            assert_eq![face.projection, Projection::Perspective];
+            assert![close(face.direction(0.5, 0.5), *axis)];
+
+            // 90 degrees per face, the edges are shared with the neighbours
+            let edge = face.direction(1., 0.5);
+            assert![(edge.dot(*axis) - (PI / 4.).cos()).abs() < 1e-9];
+        }
+
+        // Side faces upright, looking back along +z puts +x on the left
+        assert![faces[0].direction(0.5, 0.).y > 0.];
+        assert![close(
+            faces[4].direction(0., 0.5),
+            vec(1., 0., 1.).normalized()
+        )];
+        assert_eq![rig.view_at(0.9, 0.9), 5];
+        assert_eq![rig.view_at(0.4, 0.2), 1];
+    }
+}
@@ -0,0 +1,435 @@
+use geometry::Vec3f;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// Small, fast and reproducible random number generator (PCG32)
+// See http://www.pcg-random.org/
+// Every pixel gets its own generator, so that renders do not depend on the thread scheduling
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+impl Rng {
+    pub fn create(seed: u64) -> Rng {
+        // Scramble the seed a bit, neighbouring pixels use consecutive seeds
+        let mut rng = Rng {
+            state: seed.wrapping_add(INCREMENT),
+        };
+        rng.next_u32();
+        rng.state = rng
+            .state
+            .wrapping_add(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // Uniform in [0, 1[
+    pub fn next_f64(&mut self) -> f64 {
+        f64::from(self.next_u32()) / 4_294_967_296.
+    }
+}
+
+// How the samples are spread over a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplePattern {
+    Grid,   // Regular, cell centers
+    Jitter, // Stratified, one random sample per cell
+    Halton, // Low discrepancy, bases 2 and 3, randomly shifted per pixel
+    Sobol,  // Low discrepancy, first two dimensions, randomly scrambled per pixel
+}
+
+// Radical inverse in base 2, mirrors the bits around the decimal point
+fn van_der_corput(index: u32, scramble: u32) -> f64 {
+    f64::from(index.reverse_bits() ^ scramble) / 4_294_967_296.
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inv_base = 1. / f64::from(base);
+    let mut digit_weight = inv_base;
+    let mut value = 0.;
+    while index > 0 {
+        value += f64::from(index % base) * digit_weight;
+        index /= base;
+        digit_weight *= inv_base;
+    }
+    value
+}
+
+// Second dimension of the Sobol sequence, primitive polynomial x + 1
+fn sobol_2(mut index: u32, scramble: u32) -> f64 {
+    let mut direction: u32 = 1 << 31;
+    let mut value = scramble;
+    while index > 0 {
+        if index & 1 == 1 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    f64::from(value) / 4_294_967_296.
+}
+
+// Cells tiling the unit square, n of them with the same area: (x, y, width, height).
+// As square as possible, the rows which cannot be full get fewer, wider cells and
+// are taller in proportion, so that no part of the pixel is left out
+fn strata(n: usize) -> Vec<(f64, f64, f64, f64)> {
+    let rows = ((n as f64).sqrt() as usize).max(1);
+    let mut cells = Vec::with_capacity(n);
+    let mut y = 0.;
+    for row in 0..rows {
+        let columns = n / rows + usize::from(row < n % rows);
+        let height = columns as f64 / n as f64;
+        for column in 0..columns {
+            let width = 1. / columns as f64;
+            cells.push((column as f64 * width, y, width, height));
+        }
+        y += height;
+    }
+    cells
+}
+
+// Wrap around to [0, 1[
+fn shifted(x: f64, shift: f64) -> f64 {
+    let y = x + shift;
+    y - y.floor()
+}
+
+impl SamplePattern {
+    // n sample positions in the unit square
+    pub fn samples(&self, n: usize, rng: &mut Rng) -> Vec<(f64, f64)> {
+        let scramble = self.scramble(rng);
+        self.samples_from(0, n, scramble, rng)
+    }
+
+    // Random offsets of the low discrepancy sequences, drawn once per pixel
+    pub fn scramble(&self, rng: &mut Rng) -> (u32, u32) {
+        match self {
+            SamplePattern::Halton | SamplePattern::Sobol => (rng.next_u32(), rng.next_u32()),
+            SamplePattern::Grid | SamplePattern::Jitter => (0, 0),
+        }
+    }
+
+    // n more sample positions in the unit square, once first ones were already taken with
+    // the same scramble. The low discrepancy sequences carry on where they stopped, the grid
+    // would fall on the very same positions again and gets jittered instead
+    pub fn samples_from(
+        &self,
+        first: usize,
+        n: usize,
+        scramble: (u32, u32),
+        rng: &mut Rng,
+    ) -> Vec<(f64, f64)> {
+        let indices = first as u32..(first + n) as u32;
+        match self {
+            SamplePattern::Grid if first > 0 => {
+                SamplePattern::Jitter.samples_from(first, n, scramble, rng)
+            }
+            SamplePattern::Grid => strata(n)
+                .into_iter()
+                .map(|(x, y, width, height)| (x + 0.5 * width, y + 0.5 * height))
+                .collect(),
+            SamplePattern::Jitter => strata(n)
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    (x + rng.next_f64() * width, y + rng.next_f64() * height)
+                })
+                .collect(),
+            SamplePattern::Halton => {
+                let shift_x = f64::from(scramble.0) / 4_294_967_296.;
+                let shift_y = f64::from(scramble.1) / 4_294_967_296.;
+                indices
+                    .map(|k| {
+                        (
+                            shifted(radical_inverse(k, 2), shift_x),
+                            shifted(radical_inverse(k, 3), shift_y),
+                        )
+                    })
+                    .collect()
+            }
+            SamplePattern::Sobol => indices
+                .map(|k| (van_der_corput(k, scramble.0), sobol_2(k, scramble.1)))
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for SamplePattern {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<SamplePattern, String> {
+        match name {
+            "grid" => Ok(SamplePattern::Grid),
+            "jitter" => Ok(SamplePattern::Jitter),
+            "halton" => Ok(SamplePattern::Halton),
+            "sobol" => Ok(SamplePattern::Sobol),
+            _ => Err(format!("Unknown sample pattern {}", name)),
+        }
+    }
+}
+
+// Two unit vectors, orthogonal to each other and to the (unit) normal
+pub fn orthonormal_basis(normal: &Vec3f) -> (Vec3f, Vec3f) {
+    // Pick the axis the least aligned with the normal to start from
+    let helper = if normal.x.abs() > 0.9 {
+        Vec3f {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        }
+    } else {
+        Vec3f {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        }
+    };
+
+    let tangent = normal.cross(helper).normalized();
+    let bitangent = normal.cross(tangent);
+    (tangent, bitangent)
+}
+
+// Direction in the hemisphere around the normal, with a density proportional to the cosine
+// with the normal (pdf = cos / pi). u1 and u2 are uniform in [0, 1[
+pub fn cosine_hemisphere(normal: &Vec3f, u1: f64, u2: f64) -> Vec3f {
+    // Uniform on the unit disk, projected up onto the hemisphere
+    let r = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let (tangent, bitangent) = orthonormal_basis(normal);
+
+    (tangent.scaled(r * phi.cos())
+        + bitangent.scaled(r * phi.sin())
+        + normal.scaled((1. - u1).max(0.).sqrt()))
+    .normalized()
+}
+
+// Uniform on the unit disk, concentric mapping from the unit square
+// See "A low distortion map between disk and square", Shirley and Chiu, 1997
+pub fn concentric_disk(u1: f64, u2: f64) -> (f64, f64) {
+    let (a, b) = (2. * u1 - 1., 2. * u2 - 1.);
+    if a == 0. && b == 0. {
+        return (0., 0.);
+    }
+
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4. * (b / a))
+    } else {
+        (b, PI / 2. - PI / 4. * (a / b))
+    };
+    (r * phi.cos(), r * phi.sin())
+}
+
+// Uniform over a regular polygon inscribed in the unit circle, one vertex on the x axis.
+// Fewer than 3 sides falls back to the disk
+pub fn uniform_polygon(sides: usize, u1: f64, u2: f64) -> (f64, f64) {
+    if sides < 3 {
+        return concentric_disk(u1, u2);
+    }
+
+    // Pick one of the triangles fanning out of the center, reuse what is left of u1
+    let slice = u1 * sides as f64;
+    let k = (slice as usize).min(sides - 1);
+    let (mut s, mut t) = (slice - k as f64, u2);
+    if s + t > 1. {
+        s = 1. - s;
+        t = 1. - t;
+    }
+
+    let angle = 2. * PI / sides as f64;
+    let (a0, a1) = (k as f64 * angle, (k + 1) as f64 * angle);
+    (s * a0.cos() + t * a1.cos(), s * a0.sin() + t * a1.sin())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::create(12);
+        let n = 10000;
+        let mut sum = 0.;
+        for _ in 0..n {
+            let x = rng.next_f64();
+            assert![(0. ..1.).contains(&x)];
+            sum += x;
+        }
+        assert![(sum / n as f64 - 0.5).abs() < 0.02];
+
+        // Reproducible, and different from one seed to the next
+        assert_eq![Rng::create(3).next_u32(), Rng::create(3).next_u32()];
+        assert![Rng::create(3).next_u32() != Rng::create(4).next_u32()];
+    }
+
+    #[test]
+    fn test_cosine_hemisphere() {
+        let normal = Vec3f {
+            x: 1.,
+            y: 2.,
+            z: -0.5,
+        }
+        .normalized();
+
+        let (t, b) = orthonormal_basis(&normal);
+        assert![t.dot(normal).abs() < 1e-12];
+        assert![b.dot(normal).abs() < 1e-12];
+        assert![t.dot(b).abs() < 1e-12];
+
+        // All samples above the surface, and E[cos] = 2/3 for a cosine distribution
+        let mut rng = Rng::create(0);
+        let n = 20000;
+        let mut sum = 0.;
+        for _ in 0..n {
+            let dir = cosine_hemisphere(&normal, rng.next_f64(), rng.next_f64());
+            assert![(dir.squared_norm() - 1.).abs() < 1e-9];
+            assert![dir.dot(normal) >= 0.];
+            sum += dir.dot(normal);
+        }
+        assert![(sum / n as f64 - 2. / 3.).abs() < 0.01];
+    }
+
+    #[test]
+    fn test_low_discrepancy() {
+        assert_eq![radical_inverse(1, 2), 0.5];
+        assert_eq![radical_inverse(2, 2), 0.25];
+        assert_eq![radical_inverse(3, 2), 0.75];
+        assert![(radical_inverse(1, 3) - 1. / 3.).abs() < 1e-12];
+        assert![(radical_inverse(5, 3) - 7. / 9.).abs() < 1e-12];
+
+        // Both Sobol dimensions are (0, 1) sequences: in base 2, every aligned block
+        // of 2^k points falls once in each interval of size 1/2^k
+        for k in 0..16 {
+            assert_eq![van_der_corput(k, 0), radical_inverse(k, 2)];
+        }
+        let mut seen = [false; 16];
+        for k in 0..16 {
+            seen[(sobol_2(k, 0) * 16.) as usize] = true;
+        }
+        assert![seen.iter().all(|s| *s)];
+    }
+
+    #[test]
+    fn test_sample_patterns() {
+        let patterns = [
+            SamplePattern::Grid,
+            SamplePattern::Jitter,
+            SamplePattern::Halton,
+            SamplePattern::Sobol,
+        ];
+
+        for pattern in patterns.iter() {
+            let mut rng = Rng::create(7);
+            for n in [1, 4, 5, 16].iter() {
+                let samples = pattern.samples(*n, &mut rng);
+                assert_eq![samples.len(), *n];
+                assert![samples
+                    .iter()
+                    .all(|(x, y)| (0. ..1.).contains(x) && (0. ..1.).contains(y))];
+            }
+
+            // Stratified: 16 samples, one per cell of a 4x4 grid
+            let samples = pattern.samples(16, &mut rng);
+            let mut cells = [0; 16];
+            for (x, y) in samples {
+                cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+            }
+            if *pattern != SamplePattern::Halton {
+                assert![cells.iter().all(|c| *c == 1)];
+            }
+            assert![cells.iter().filter(|c| **c > 0).count() >= 12];
+        }
+
+        // Whatever the count, the strata cover the whole pixel evenly
+        for n in [2, 3, 5, 7].iter() {
+            let mean = |samples: Vec<(f64, f64)>| {
+                let count = samples.len() as f64;
+                samples
+                    .iter()
+                    .fold((0., 0.), |m, s| (m.0 + s.0 / count, m.1 + s.1 / count))
+            };
+            let (x, y) = mean(SamplePattern::Grid.samples(*n, &mut Rng::create(0)));
+            assert![(x - 0.5).abs() < 1e-12 && (y - 0.5).abs() < 1e-12];
+
+            let mut rng = Rng::create(3);
+            let jittered = (0..2000)
+                .flat_map(|_| SamplePattern::Jitter.samples(*n, &mut rng))
+                .collect();
+            let (x, y) = mean(jittered);
+            assert![(x - 0.5).abs() < 0.01 && (y - 0.5).abs() < 0.01];
+
+            let cells = strata(*n);
+            assert_eq![cells.len(), *n];
+            let area: f64 = cells.iter().map(|c| c.2 * c.3).sum();
+            assert![(area - 1.).abs() < 1e-12];
+            assert![cells
+                .iter()
+                .all(|c| (c.2 * c.3 - 1. / *n as f64).abs() < 1e-12)];
+        }
+
+        // In batches, the sequences carry on instead of starting over
+        let mut rng = Rng::create(9);
+        for pattern in patterns.iter() {
+            let scramble = pattern.scramble(&mut rng);
+            let first = pattern.samples_from(0, 4, scramble, &mut rng);
+            let next = pattern.samples_from(4, 4, scramble, &mut rng);
+            assert![next.iter().all(|s| !first.contains(s))];
+            if *pattern == SamplePattern::Sobol {
+                let whole = pattern.samples_from(0, 8, scramble, &mut rng);
+                assert_eq![whole, [first, next].concat()];
+            }
+        }
+
+        // A single sample sits in the middle of the pixel
+        assert_eq![
+            SamplePattern::Grid.samples(1, &mut Rng::create(0)),
+            vec![(0.5, 0.5)]
+        ];
+        assert_eq!["sobol".parse::<SamplePattern>(), Ok(SamplePattern::Sobol)];
+        assert!["poisson".parse::<SamplePattern>().is_err()];
+    }
+
+    #[test]
+    fn test_lens_sampling() {
+        let mut rng = Rng::create(5);
+        let n = 20000;
+
+        // Inside the unit disk, and uniform: a quarter of the samples within half the radius
+        let mut inner = 0;
+        for _ in 0..n {
+            let (x, y) = concentric_disk(rng.next_f64(), rng.next_f64());
+            assert![x * x + y * y <= 1. + 1e-12];
+            if x * x + y * y < 0.25 {
+                inner += 1;
+            }
+        }
+        assert![(inner as f64 / n as f64 - 0.25).abs() < 0.02];
+
+        // Hexagon: inside the apothem in every direction, and in every sector
+        let apothem = (PI / 6.).cos();
+        let mut sectors = [0; 6];
+        for _ in 0..n {
+            let (x, y) = uniform_polygon(6, rng.next_f64(), rng.next_f64());
+            for k in 0..6 {
+                let normal = (2. * k as f64 + 1.) * PI / 6.;
+                assert![x * normal.cos() + y * normal.sin() <= apothem + 1e-12];
+            }
+            let angle = y.atan2(x).rem_euclid(2. * PI);
+            sectors[(angle / (PI / 3.)) as usize % 6] += 1;
+        }
+        assert![sectors
+            .iter()
+            .all(|s| (*s as f64 / n as f64 - 1. / 6.).abs() < 0.02)];
+    }
+}
@@ -1,15 +1,52 @@
+use camera::Camera;
 use geometry;
 use geometry::Vec3f;
 use lights;
+use obj;
 use polygon;
+use procedural;
+use sdf;
+use sdf::*;
+use shapes::BoundingBox;
 use shapes::Reflectance;
 use shapes::Shape;
 use sphere;
+use std::sync::Arc;
 
 pub struct Scene {
     pub lights: Vec<lights::Light>,
     pub shapes: Vec<Box<dyn Shape + Sync>>,
-    pub camera: geometry::Vec3f,
+    pub camera: Camera,
+}
+
+// Look at the whole box from its -z side, the way the Cornell box is usually framed
+fn frame_bounding_box(bb: &BoundingBox, camera: &Camera) -> Camera {
+    let center = bb.middle();
+    let extent = bb.max - bb.min;
+
+    // Far enough for the front face to fit in the field of view
+    let half_tan = (camera.vfov / 2.).tan();
+    let half_size = (0.5 * extent.y).max(0.5 * extent.x / camera.aspect);
+    let distance = 0.5 * extent.z + 1.1 * half_size / half_tan;
+
+    Camera::create(
+        center
+            - Vec3f {
+                x: 0.,
+                y: 0.,
+                z: distance.max(1e-3),
+            },
+        center,
+        camera.up,
+        camera.vfov,
+        camera.aspect,
+    )
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Scene::new()
+    }
 }
 
 impl Scene {
@@ -18,12 +55,66 @@ impl Scene {
         Scene {
             lights: vec![],
             shapes: vec![],
-            camera: geometry::Vec3f::zero(),
+            camera: Camera::create_default(),
+        }
+    }
+
+    // Framed for the given vertical field of view and aspect ratio.
+    // Fails if the file cannot be loaded, or holds no object
+    pub fn create_from_obj(filepath: String, vfov: f64, aspect: f64) -> Result<Scene, String> {
+        let mut scene = Scene::new();
+        scene.camera.vfov = vfov;
+        scene.camera.aspect = aspect;
+
+        let objects = match obj::load(filepath.clone()) {
+            Some(objects) => objects,
+            None => return Err(format!("Could not load {}", filepath)),
+        };
+
+        let mut bounding_box: Option<BoundingBox> = None;
+        for obj in objects {
+            match bounding_box {
+                Some(ref mut bb) => bb.merge(&obj.bounding_box()),
+                None => bounding_box = Some(obj.bounding_box()),
+            }
+            // `Box` moves storage to the heap
+            scene.shapes.push(Box::new(obj));
         }
+
+        match bounding_box {
+            Some(bb) => scene.camera = frame_bounding_box(&bb, &scene.camera),
+            None => return Err(format!("No object in {}", filepath)),
+        }
+
+        // Add an arbitrary set of lights, next to the camera
+        // FIXME: Need something a tiny bit better
+        let position = scene.camera.position;
+        scene.lights.push(lights::create_light(
+            position,
+            Vec3f::ones(), // white light
+            1.,
+        ));
+
+        scene.lights.push(lights::create_light(
+            position
+                + Vec3f {
+                    x: 20.,
+                    y: 20.,
+                    z: 20.,
+                },
+            Vec3f {
+                x: 1.,
+                y: 0.5,
+                z: 0.5,
+            }, // reddish light
+            0.8,
+        ));
+
+        Ok(scene)
     }
 
     pub fn offset_camera(&mut self, offset: geometry::Vec3f) {
-        self.camera += offset;
+        self.camera.offset(offset);
     }
     pub fn create_default() -> Scene {
         let mut reflectance = Reflectance::create_default();
@@ -43,7 +134,7 @@ impl Scene {
                 z: -16.,
             },
             4.,
-            reflectance,
+            reflectance.clone(),
         );
 
         // polygon
@@ -70,7 +161,7 @@ impl Scene {
                     z: -8.,
                 },
             ],
-            reflectance,
+            reflectance.clone(),
         );
 
         // Floor
@@ -84,6 +175,12 @@ impl Scene {
             y: 0.9,
             z: 0.9,
         };
+        reflectance.diffuse_texture = Some(Arc::new(procedural::Checker {
+            even: Vec3f::ones(),
+            odd: Vec3f::ones().scaled(0.3),
+            scale: 8.,
+            mapping: procedural::Mapping::Uv,
+        }));
 
         let square = polygon::ConvexPolygon::create(
             vec![
@@ -108,10 +205,11 @@ impl Scene {
                     z: -3.,
                 },
             ],
-            reflectance,
+            reflectance.clone(),
         );
 
         // Blue sphere
+        reflectance.diffuse_texture = None;
         reflectance.specular = 1.0;
         reflectance.diffusion = 0.1;
         reflectance.diffuse_color = Vec3f {
@@ -130,7 +228,7 @@ impl Scene {
                 z: -5.,
             },
             2.,
-            reflectance,
+            reflectance.clone(),
         );
 
         // Green sphere
@@ -152,7 +250,7 @@ impl Scene {
                 z: -18.,
             },
             3.,
-            reflectance,
+            reflectance.clone(),
         );
 
         // White sphere
@@ -168,7 +266,7 @@ impl Scene {
                 z: -14.,
             },
             4.,
-            reflectance,
+            reflectance.clone(),
         );
 
         // Add a light to the scene
@@ -206,7 +304,205 @@ impl Scene {
                 Box::new(triangle),
                 Box::new(square),
             ],
-            camera: geometry::Vec3f::zero(),
+            camera: Camera::create_default(),
+        }
+    }
+
+    // Implicit surfaces, rendered through sphere tracing
+    pub fn create_implicit() -> Scene {
+        let mut reflectance = Reflectance::create_default();
+
+        // Fractal in the middle
+        reflectance.diffuse_color = Vec3f {
+            x: 0.9,
+            y: 0.6,
+            z: 0.3,
+        };
+        reflectance.specular_exponent = 50.;
+        let mandelbulb = sdf::create(
+            Box::new(Mandelbulb {
+                center: Vec3f {
+                    x: 0.,
+                    y: 0.,
+                    z: -10.,
+                },
+                scale: 3.,
+                power: 8.,
+                iterations: 10,
+            }),
+            reflectance.clone(),
+        );
+
+        // A rounded box melting into a sphere on the left
+        reflectance.diffuse_color = Vec3f {
+            x: 0.2,
+            y: 0.4,
+            z: 0.9,
+        };
+        let blob = sdf::create(
+            Box::new(Combination {
+                a: Box::new(SdfBox {
+                    center: Vec3f {
+                        x: -7.,
+                        y: -1.,
+                        z: -14.,
+                    },
+                    half_size: Vec3f::ones().scaled(1.5),
+                    rounding: 0.2,
+                }),
+                b: Box::new(SdfSphere {
+                    center: Vec3f {
+                        x: -7.,
+                        y: 1.,
+                        z: -14.,
+                    },
+                    radius: 1.5,
+                }),
+                operation: Operation::Union,
+                smoothness: 1.,
+            }),
+            reflectance.clone(),
+        );
+
+        // A torus pierced by a capsule on the right
+        reflectance.diffuse_color = Vec3f {
+            x: 0.3,
+            y: 0.9,
+            z: 0.3,
+        };
+        let torus = sdf::create(
+            Box::new(Combination {
+                a: Box::new(SdfTorus {
+                    center: Vec3f {
+                        x: 7.,
+                        y: 0.,
+                        z: -14.,
+                    },
+                    major_radius: 2.,
+                    minor_radius: 0.6,
+                }),
+                b: Box::new(SdfCapsule {
+                    a: Vec3f {
+                        x: 5.,
+                        y: 0.,
+                        z: -14.,
+                    },
+                    b: Vec3f {
+                        x: 9.,
+                        y: 0.,
+                        z: -14.,
+                    },
+                    radius: 0.8,
+                }),
+                operation: Operation::Subtraction,
+                smoothness: 0.2,
+            }),
+            reflectance.clone(),
+        );
+
+        // Floor
+        reflectance.diffuse_color = Vec3f {
+            x: 0.8,
+            y: 0.8,
+            z: 0.8,
+        };
+        let floor = sdf::create(
+            Box::new(SdfPlane {
+                normal: Vec3f {
+                    x: 0.,
+                    y: 1.,
+                    z: 0.,
+                },
+                offset: -3.,
+            }),
+            reflectance.clone(),
+        );
+
+        let light_white = lights::create_light(
+            Vec3f {
+                x: 0.,
+                y: 10.,
+                z: 0.,
+            },
+            Vec3f::ones(), // white light
+            1.,
+        );
+
+        Scene {
+            lights: vec![light_white],
+            shapes: vec![
+                Box::new(mandelbulb),
+                Box::new(blob),
+                Box::new(torus),
+                Box::new(floor),
+            ],
+            camera: Camera::create_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use framebuffer::create_frame_buffer;
+    use integrator;
+    use renderer::create_renderer;
+
+    #[test]
+    fn test_missing_obj() {
+        let scene = Scene::create_from_obj(String::from("../test_data/missing.obj"), 1.5, 1.);
+        assert![scene.is_err()];
+
+        let scene =
+            Scene::create_from_obj(String::from("../test_data/cornell_box.obj"), 1.5, 1.).unwrap();
+        assert![!scene.shapes.is_empty()];
+    }
+
+    #[test]
+    fn test_obj_framing() {
+        // Narrow field of view, wide picture
+        let (width, height) = (36, 24);
+        let scene = Scene::create_from_obj(
+            String::from("../test_data/cornell_box.obj"),
+            0.5,
+            width as f64 / height as f64,
+        )
+        .unwrap();
+        assert_eq![scene.camera.vfov, 0.5];
+
+        // Every corner of the bounding box is in view
+        let mut bb = scene.shapes[0].bounding_box();
+        for shape in &scene.shapes {
+            bb.merge(&shape.bounding_box());
+        }
+        let (right, up, forward) = scene.camera.basis();
+        let half_tan = (0.25f64).tan();
+        for k in 0..8 {
+            let corner = Vec3f {
+                x: if k & 1 == 0 { bb.min.x } else { bb.max.x },
+                y: if k & 2 == 0 { bb.min.y } else { bb.max.y },
+                z: if k & 4 == 0 { bb.min.z } else { bb.max.z },
+            };
+            let to_corner = corner - scene.camera.position;
+            let depth = to_corner.dot(forward);
+            assert![depth > 0.];
+            assert![to_corner.dot(up).abs() / depth < half_tan];
+            assert![to_corner.dot(right).abs() / depth < half_tan * 1.5];
+        }
+
+        // Rendered, the box does not touch the edges of the picture
+        let mut renderer = create_renderer(height as f64, width as f64);
+        renderer.integrator = integrator::create("normals").unwrap();
+        let mut frame = create_frame_buffer(width, height);
+        renderer.render(&mut frame, &scene);
+
+        let black = |j: usize, i: usize| frame.buffer[j][i] == Vec3f::zero();
+        for i in 0..width {
+            assert![black(0, i) && black(height - 1, i)];
+        }
+        for j in 0..height {
+            assert![black(j, 0) && black(j, width - 1)];
         }
+        assert![!black(height / 2, width / 2)];
     }
 }
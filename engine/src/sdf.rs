@@ -0,0 +1,544 @@
+use geometry::Vec3f;
+use shapes::*;
+
+// Signed distance fields, rendered through sphere tracing.
+// See https://iquilezles.org/articles/distfunctions/ for most of the formulas
+// and https://en.wikipedia.org/wiki/Ray_marching for the marching itself
+
+// Extent given to the unbounded fields (planes, repetitions)
+pub const FAR: f64 = 1e4;
+
+pub trait Sdf {
+    // Signed distance to the surface, negative inside.
+    // Can underestimate the true distance (fractals), never overestimate it
+    fn distance(&self, p: &Vec3f) -> f64;
+
+    // Box enclosing the surface, the ray marching is limited to it
+    fn bounding_box(&self) -> BoundingBox;
+
+    // Outward normal, the gradient of the field computed by central differences
+    fn normal(&self, p: &Vec3f) -> Vec3f {
+        let h = 1e-6;
+        let dx = Vec3f { x: h, y: 0., z: 0. };
+        let dy = Vec3f { x: 0., y: h, z: 0. };
+        let dz = Vec3f { x: 0., y: 0., z: h };
+
+        Vec3f {
+            x: self.distance(&(*p + dx)) - self.distance(&(*p - dx)),
+            y: self.distance(&(*p + dy)) - self.distance(&(*p - dy)),
+            z: self.distance(&(*p + dz)) - self.distance(&(*p - dz)),
+        }
+        .normalized()
+    }
+}
+
+fn unbounded_box() -> BoundingBox {
+    BoundingBox {
+        min: Vec3f::ones().scaled(-FAR),
+        max: Vec3f::ones().scaled(FAR),
+    }
+}
+
+fn padded_box(bb: BoundingBox, padding: f64) -> BoundingBox {
+    BoundingBox {
+        min: bb.min - Vec3f::ones().scaled(padding),
+        max: bb.max + Vec3f::ones().scaled(padding),
+    }
+}
+
+// ************************************************************
+// Primitives
+// ************************************************************
+
+pub struct SdfSphere {
+    pub center: Vec3f,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        (*p - self.center).norm() - self.radius
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        padded_box(BoundingBox::create(self.center), self.radius)
+    }
+}
+
+// Axis aligned box, possibly with rounded edges
+pub struct SdfBox {
+    pub center: Vec3f,
+    pub half_size: Vec3f,
+    pub rounding: f64,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        let q = (*p - self.center).abs() - self.half_size;
+        let outside = Vec3f {
+            x: q.x.max(0.),
+            y: q.y.max(0.),
+            z: q.z.max(0.),
+        };
+        outside.norm() + q.x.max(q.y).max(q.z).min(0.) - self.rounding
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        padded_box(
+            BoundingBox {
+                min: self.center - self.half_size,
+                max: self.center + self.half_size,
+            },
+            self.rounding,
+        )
+    }
+}
+
+// Torus lying in the xz plane
+pub struct SdfTorus {
+    pub center: Vec3f,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        let q = *p - self.center;
+        let ring = (q.x * q.x + q.z * q.z).sqrt() - self.major_radius;
+        (ring * ring + q.y * q.y).sqrt() - self.minor_radius
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let extent = Vec3f {
+            x: self.major_radius + self.minor_radius,
+            y: self.minor_radius,
+            z: self.major_radius + self.minor_radius,
+        };
+        BoundingBox {
+            min: self.center - extent,
+            max: self.center + extent,
+        }
+    }
+}
+
+// Segment [a, b] inflated by radius
+pub struct SdfCapsule {
+    pub a: Vec3f,
+    pub b: Vec3f,
+    pub radius: f64,
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        let pa = *p - self.a;
+        let ba = self.b - self.a;
+        let h = (pa.dot(ba) / ba.squared_norm()).clamp(0., 1.);
+        (pa - ba.scaled(h)).norm() - self.radius
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let mut bb = BoundingBox::create(self.a);
+        bb.update(&self.b);
+        padded_box(bb, self.radius)
+    }
+}
+
+// Infinite plane, points such that p.normal = offset
+pub struct SdfPlane {
+    pub normal: Vec3f,
+    pub offset: f64,
+}
+
+impl Sdf for SdfPlane {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        p.dot(self.normal.normalized()) - self.offset
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        unbounded_box()
+    }
+}
+
+// Mandelbulb fractal, see https://en.wikipedia.org/wiki/Mandelbulb
+pub struct Mandelbulb {
+    pub center: Vec3f,
+    pub scale: f64,
+    pub power: f64,
+    pub iterations: usize,
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        // Distance estimator, computed in the fractal frame
+        let c = (*p - self.center).scaled(1. / self.scale);
+        let mut z = c;
+        let mut dr = 1.;
+        let mut r = z.norm();
+
+        for _ in 0..self.iterations {
+            if !(1e-12..=2.).contains(&r) {
+                break;
+            }
+
+            // Move to polar coordinates, scale and rotate
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+
+            z = Vec3f {
+                x: theta.sin() * phi.cos(),
+                y: theta.sin() * phi.sin(),
+                z: theta.cos(),
+            }
+            .scaled(r.powf(self.power))
+                + c;
+            r = z.norm();
+        }
+
+        if r < 1e-12 {
+            return 0.;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        padded_box(BoundingBox::create(self.center), 1.2 * self.scale)
+    }
+}
+
+// ************************************************************
+// Combinations
+// ************************************************************
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Subtraction, // First field minus the second one
+}
+
+// Boolean combination of two fields, blended over `smoothness` (0 for sharp edges)
+pub struct Combination {
+    pub a: Box<dyn Sdf + Sync>,
+    pub b: Box<dyn Sdf + Sync>,
+    pub operation: Operation,
+    pub smoothness: f64,
+}
+
+fn mix(a: f64, b: f64, h: f64) -> f64 {
+    a * (1. - h) + b * h
+}
+
+impl Sdf for Combination {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let k = self.smoothness;
+
+        if k <= 0. {
+            return match self.operation {
+                Operation::Union => d1.min(d2),
+                Operation::Intersection => d1.max(d2),
+                Operation::Subtraction => d1.max(-d2),
+            };
+        }
+
+        // Polynomial smooth min/max
+        match self.operation {
+            Operation::Union => {
+                let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                mix(d2, d1, h) - k * h * (1. - h)
+            }
+            Operation::Intersection => {
+                let h = (0.5 - 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                mix(d2, d1, h) + k * h * (1. - h)
+            }
+            Operation::Subtraction => {
+                let h = (0.5 - 0.5 * (d1 + d2) / k).clamp(0., 1.);
+                mix(d1, -d2, h) + k * h * (1. - h)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        match self.operation {
+            Operation::Union => {
+                let mut bb = self.a.bounding_box();
+                bb.merge(&self.b.bounding_box());
+                padded_box(bb, self.smoothness)
+            }
+            // Both can only shrink the first field
+            Operation::Intersection | Operation::Subtraction => self.a.bounding_box(),
+        }
+    }
+}
+
+// Infinite repetition of a field, centered on the origin, every `period`
+pub struct Repetition {
+    pub sdf: Box<dyn Sdf + Sync>,
+    pub period: Vec3f,
+}
+
+impl Sdf for Repetition {
+    fn distance(&self, p: &Vec3f) -> f64 {
+        // Zero periods mean no repetition along this axis
+        let wrap = |v: f64, period: f64| {
+            if period > 0. {
+                v - period * (v / period).round()
+            } else {
+                v
+            }
+        };
+
+        self.sdf.distance(&Vec3f {
+            x: wrap(p.x, self.period.x),
+            y: wrap(p.y, self.period.y),
+            z: wrap(p.z, self.period.z),
+        })
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        unbounded_box()
+    }
+}
+
+// ************************************************************
+// Shape adapter, sphere tracing
+// ************************************************************
+
+pub struct SdfShape {
+    sdf: Box<dyn Sdf + Sync>,
+    reflectance: Reflectance,
+    bounding_box: BoundingBox,
+    pub max_steps: usize,
+    pub epsilon: f64,
+}
+
+pub fn create(sdf: Box<dyn Sdf + Sync>, reflectance: Reflectance) -> SdfShape {
+    let bounding_box = sdf.bounding_box();
+    SdfShape {
+        sdf,
+        reflectance,
+        bounding_box,
+        max_steps: 256,
+        epsilon: 1e-5,
+    }
+}
+
+impl Shape for SdfShape {
+    fn intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<Intersection> {
+        // Direction needs to be normalized
+        assert![(dir.squared_norm() - 1.).abs() < 1e-4];
+
+        // Only march within the bounding box
+        let mut dist = self.bounding_box.intersect(orig, dir)?;
+        let max_dist = dist + (self.bounding_box.max - self.bounding_box.min).norm();
+
+        // Rays can start from within the object (refraction),
+        // in which case the field is followed with its sign flipped
+        let sign = if self.sdf.distance(orig) < 0. {
+            -1.
+        } else {
+            1.
+        };
+
+        for _ in 0..self.max_steps {
+            let point = *orig + dir.scaled(dist);
+            let step = sign * self.sdf.distance(&point);
+
+            if step < self.epsilon {
+                return Some(Intersection {
+                    point,
+                    normal: self.sdf.normal(&point),
+                    reflectance: self.reflectance.clone(),
+                    barycentric: None,
+                    uv: None,
+                });
+            }
+
+            dist += step;
+            if dist > max_dist {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        self.bounding_box.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sphere;
+
+    fn forward() -> Vec3f {
+        Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        }
+    }
+
+    fn point(x: f64, y: f64, z: f64) -> Vec3f {
+        Vec3f { x, y, z }
+    }
+
+    #[test]
+    fn test_sphere_matches_analytic() {
+        let center = point(0.5, -0.3, -10.);
+        let analytic = sphere::create(center, 2., Reflectance::create_default());
+        let marched = create(
+            Box::new(SdfSphere { center, radius: 2. }),
+            Reflectance::create_default(),
+        );
+
+        for i in 0..20 {
+            for j in 0..20 {
+                let dir = point(i as f64 / 50. - 0.2, j as f64 / 50. - 0.2, -1.).normalized();
+                let a = analytic.intersect(&Vec3f::zero(), &dir);
+                let m = marched.intersect(&Vec3f::zero(), &dir);
+
+                assert_eq![a.is_some(), m.is_some()];
+                if let (Some(a), Some(m)) = (a, m) {
+                    // Marching stops close to the surface, which is less precise
+                    // along the ray for grazing angles
+                    assert![((m.point - center).norm() - 2.).abs() < 1e-4];
+                    assert![(a.point - m.point).norm() < 1e-2];
+                    assert![(a.normal - m.normal).norm() < 1e-2];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_primitive_distances() {
+        let b = SdfBox {
+            center: Vec3f::zero(),
+            half_size: Vec3f::ones(),
+            rounding: 0.,
+        };
+        assert![(b.distance(&point(3., 0., 0.)) - 2.).abs() < 1e-9];
+        assert![(b.distance(&point(0.5, 0., 0.)) + 0.5).abs() < 1e-9];
+        assert![(b.distance(&point(2., 2., 1.)) - 2f64.sqrt()).abs() < 1e-9];
+
+        let torus = SdfTorus {
+            center: Vec3f::zero(),
+            major_radius: 2.,
+            minor_radius: 0.5,
+        };
+        assert![torus.distance(&point(2., 0., 0.)) + 0.5 < 1e-9];
+        assert![(torus.distance(&Vec3f::zero()) - 1.5).abs() < 1e-9];
+
+        let capsule = SdfCapsule {
+            a: Vec3f::zero(),
+            b: point(0., 2., 0.),
+            radius: 0.5,
+        };
+        assert![(capsule.distance(&point(1., 1., 0.)) - 0.5).abs() < 1e-9];
+        assert![(capsule.distance(&point(0., 4., 0.)) - 1.5).abs() < 1e-9];
+
+        let plane = SdfPlane {
+            normal: point(0., 1., 0.),
+            offset: -1.,
+        };
+        assert![(plane.distance(&point(3., 2., -5.)) - 3.).abs() < 1e-9];
+    }
+
+    #[test]
+    fn test_combinations() {
+        let sphere = |x: f64| -> Box<dyn Sdf + Sync> {
+            Box::new(SdfSphere {
+                center: point(x, 0., 0.),
+                radius: 1.,
+            })
+        };
+
+        let p = point(0.5, 0.5, 0.);
+        let (d1, d2) = (sphere(0.).distance(&p), sphere(1.).distance(&p));
+
+        for smoothness in &[0., 0.5] {
+            let combine = |operation| Combination {
+                a: sphere(0.),
+                b: sphere(1.),
+                operation,
+                smoothness: *smoothness,
+            };
+
+            // Smooth blending only ever adds matter to the union, removes it otherwise
+            assert![combine(Operation::Union).distance(&p) <= d1.min(d2) + 1e-12];
+            assert![combine(Operation::Intersection).distance(&p) >= d1.max(d2) - 1e-12];
+            assert![combine(Operation::Subtraction).distance(&p) >= d1.max(-d2) - 1e-12];
+        }
+
+        // Far from the blending region, smooth and sharp agree
+        let union = Combination {
+            a: sphere(0.),
+            b: sphere(10.),
+            operation: Operation::Union,
+            smoothness: 0.5,
+        };
+        assert![(union.distance(&point(-3., 0., 0.)) - 2.).abs() < 1e-9];
+    }
+
+    #[test]
+    fn test_repetition() {
+        let repeated = Repetition {
+            sdf: Box::new(SdfSphere {
+                center: Vec3f::zero(),
+                radius: 1.,
+            }),
+            period: point(4., 0., 4.),
+        };
+
+        let p = point(0.3, 0.2, 1.5);
+        let d = repeated.distance(&p);
+        assert![(repeated.distance(&(p + point(8., 0., -4.))) - d).abs() < 1e-9];
+        assert![(repeated.distance(&(p + point(0., 4., 0.))) - d).abs() > 1.];
+
+        // Marching through the copies, the second row is hit from above
+        let shape = create(Box::new(repeated), Reflectance::create_default());
+        let hit = shape
+            .intersect(&point(4., 5., -8.), &point(0., -1., 0.))
+            .unwrap();
+        assert![(hit.point - point(4., 1., -8.)).norm() < 1e-4];
+    }
+
+    #[test]
+    fn test_from_inside() {
+        let shape = create(
+            Box::new(SdfSphere {
+                center: point(0., 0., -5.),
+                radius: 2.,
+            }),
+            Reflectance::create_default(),
+        );
+
+        let hit = shape.intersect(&point(0., 0., -5.), &forward()).unwrap();
+        assert![(hit.point - point(0., 0., -7.)).norm() < 1e-4];
+        assert![(hit.normal - forward()).norm() < 1e-3];
+    }
+
+    #[test]
+    fn test_mandelbulb() {
+        let shape = create(
+            Box::new(Mandelbulb {
+                center: point(0., 0., -5.),
+                scale: 1.,
+                power: 8.,
+                iterations: 12,
+            }),
+            Reflectance::create_default(),
+        );
+
+        let hit = shape.intersect(&Vec3f::zero(), &forward()).unwrap();
+        assert![hit.point.z < -3.8 && hit.point.z > -5.];
+        assert![(hit.normal.norm() - 1.).abs() < 1e-6];
+
+        // The bounding box culls the rays missing the fractal
+        let away = point(1., 0., -1.).normalized();
+        assert![shape.intersect(&Vec3f::zero(), &away).is_none()];
+    }
+}
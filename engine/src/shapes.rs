@@ -1,10 +1,18 @@
 use geometry::Vec3f;
+use microfacet::Microfacet;
+use optics::Dispersion;
+use spectrum;
+use spectrum::Spectrum;
+use std::sync::Arc;
+use texture::Texture;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Intersection {
     pub point: Vec3f,
     pub normal: Vec3f,
     pub reflectance: Reflectance,
+    pub barycentric: Option<Vec3f>, // Weights of the vertices, for triangles
+    pub uv: Option<(f64, f64)>,     // Texture coordinates
 }
 
 impl Intersection {
@@ -13,22 +21,68 @@ impl Intersection {
             point: Vec3f::zero(),
             normal: Vec3f::zero(),
             reflectance: Reflectance::create_default(),
+            barycentric: None,
+            uv: None,
+        }
+    }
+
+    // Diffuse color at this point, modulated by the texture if any
+    pub fn diffuse_color(&self) -> Vec3f {
+        match self.reflectance.diffuse_texture {
+            Some(ref texture) => {
+                self.reflectance.diffuse_color * texture.value(self.uv, &self.point)
+            }
+            None => self.reflectance.diffuse_color,
+        }
+    }
+
+    // Diffuse reflectance at a given wavelength, in nanometers. The diffuse spectrum
+    // takes precedence over the color and the texture
+    pub fn diffuse_at(&self, wavelength: f64) -> f64 {
+        match self.reflectance.diffuse_spectrum {
+            Some(ref spectrum) => spectrum.eval(wavelength),
+            None => spectrum::rgb_to_spectral(self.diffuse_color(), wavelength),
+        }
+    }
+
+    // Specular coefficient at this point, modulated by the texture if any
+    pub fn specular(&self) -> f64 {
+        match self.reflectance.specular_texture {
+            Some(ref texture) => {
+                self.reflectance.specular * texture.value(self.uv, &self.point).max()
+            }
+            None => self.reflectance.specular,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Reflectance {
     // Direct lighting
     pub diffusion: f64,
     pub diffuse_color: Vec3f,   // Lambertian
     pub specular: f64,          // "hard" reflectance
     pub specular_exponent: f64, // More or less mirror-like
+    pub emission: Vec3f,        // Light emitted by the surface itself
+
+    // Optional textures, multiplying the above
+    pub diffuse_texture: Option<Arc<dyn Texture>>,
+    pub specular_texture: Option<Arc<dyn Texture>>,
+
+    // Optional spectra for the spectral rendering, the colors are upsampled otherwise
+    pub diffuse_spectrum: Option<Arc<Spectrum>>,
+    pub emission_spectrum: Option<Arc<Spectrum>>,
 
     // Reflection / refraction
     pub is_glass_like: bool,
     pub reflection: f64,
     pub refractive_index: f64,
+    pub dispersion: Option<Dispersion>, // Per channel indices, refractive_index is the average
+    pub absorption: Vec3f, // Beer-Lambert, per channel and per unit of distance travelled inside
+
+    // Physically based material, replaces the direct lighting, reflection and refraction
+    // settings above. Keeps the diffuse color (base color), refractive index and emission
+    pub microfacet: Option<Microfacet>,
 }
 
 #[derive(Clone, Debug)]
@@ -53,9 +107,58 @@ impl Reflectance {
             diffuse_color: Vec3f::ones(),
             specular: 1.,
             specular_exponent: 30.,
+            emission: Vec3f::zero(),
+            diffuse_texture: None,
+            specular_texture: None,
+            diffuse_spectrum: None,
+            emission_spectrum: None,
             is_glass_like: false,
             reflection: 0.95,
-            refractive_index: 1., // TODO: indices over R,G,B
+            refractive_index: 1.,
+            dispersion: None,
+            absorption: Vec3f::zero(),
+            microfacet: None,
+        }
+    }
+
+    // Refractive index seen by a given color channel, all of them if none
+    pub fn refractive_index_for(&self, channel: Option<usize>) -> f64 {
+        match (channel, self.dispersion) {
+            (Some(c), Some(dispersion)) => dispersion.indices().channel(c),
+            _ => self.refractive_index,
+        }
+    }
+
+    // Refractive index at a given wavelength, in nanometers
+    pub fn refractive_index_at(&self, wavelength: f64) -> f64 {
+        match self.dispersion {
+            Some(dispersion) => dispersion.index_at(wavelength / 1000.),
+            None => self.refractive_index,
+        }
+    }
+
+    // Fraction of the light going through a given distance inside the object, per channel
+    pub fn transmittance(&self, distance: f64) -> Vec3f {
+        Vec3f {
+            x: (-self.absorption.x * distance).exp(),
+            y: (-self.absorption.y * distance).exp(),
+            z: (-self.absorption.z * distance).exp(),
+        }
+    }
+
+    // Same at a given wavelength, in nanometers. The coefficients are upsampled like the colors
+    pub fn transmittance_at(&self, wavelength: f64, distance: f64) -> f64 {
+        if self.absorption == Vec3f::zero() {
+            return 1.;
+        }
+        (-spectrum::rgb_to_spectral(self.absorption, wavelength) * distance).exp()
+    }
+
+    // Emitted light at a given wavelength, in nanometers
+    pub fn emission_at(&self, wavelength: f64) -> f64 {
+        match self.emission_spectrum {
+            Some(ref spectrum) => spectrum.eval(wavelength),
+            None => spectrum::rgb_to_spectral(self.emission, wavelength),
         }
     }
 }
@@ -83,61 +186,35 @@ impl BoundingBox {
     pub fn middle(&self) -> Vec3f {
         (self.max + self.min).scaled(0.5)
     }
-}
-
-// ************************************************************
-// Some generic functions dealing with a collection of Shapes
-// ************************************************************
-
-pub fn intersect_shape_set(orig: &Vec3f, dir: &Vec3f, shapes: &[Box<dyn Shape + Sync>]) -> bool {
-    // Check wether a ray intersects with *any* shape, in no particular order
-    // Useful to compute cast shadows
-    for shape in shapes {
-        let result = shape.intersect(orig, dir);
 
-        match result {
-            Some(_intersection) => {
-                return true;
-            }
-            None => {
-                continue;
-            }
-        }
+    pub fn merge(&mut self, other: &BoundingBox) {
+        self.update(&other.min);
+        self.update(&other.max);
     }
-    false
-}
 
-pub fn find_closest_intersect(
-    orig: &Vec3f,
-    dir: Vec3f,
-    shapes: &[Box<dyn Shape + Sync>],
-) -> Option<(Intersection, u8)> {
-    // Intersect a ray with all the provided shapes,
-    // return either the intersection the closest to the ray origin,
-    // or nothing
-
-    let mut intersection_final = Intersection::create_default();
-
-    let mut hit = false;
-    let mut shape_hit = 0;
-    let mut dist_closest = 0.;
-
-    for (shape_index, shape) in shapes.iter().enumerate() {
-        let test = shape.intersect(orig, &dir);
-        if let Some(intersection) = test {
-            let dist_hit = (intersection.point - *orig).squared_norm();
-
-            if !hit || dist_hit < dist_closest {
-                intersection_final = intersection;
-                hit = true;
-                shape_hit = shape_index;
-                dist_closest = dist_hit;
-            }
+    pub fn intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<f64> {
+        // Slab test, see https://en.wikipedia.org/wiki/Slab_method
+        // returns the distance at which the ray enters the box, if it does.
+        // Zero components in the direction give infinite bounds, which behave as expected
+        let slab = |min: f64, max: f64, o: f64, d: f64| {
+            let inv = 1. / d;
+            let t1 = (min - o) * inv;
+            let t2 = (max - o) * inv;
+            (t1.min(t2), t1.max(t2))
+        };
+
+        let (x_in, x_out) = slab(self.min.x, self.max.x, orig.x, dir.x);
+        let (y_in, y_out) = slab(self.min.y, self.max.y, orig.y, dir.y);
+        let (z_in, z_out) = slab(self.min.z, self.max.z, orig.z, dir.z);
+
+        // f64::max/min ignore NaNs, which arise for a flat box in the plane of the ray
+        let t_in = x_in.max(y_in).max(z_in).max(0.);
+        let t_out = x_out.min(y_out).min(z_out);
+
+        // Small tolerance, so that flat boxes (axis aligned polygons) are not missed
+        if t_in > t_out + 1e-6 {
+            return None;
         }
+        Some(t_in)
     }
-
-    if hit {
-        return Some((intersection_final, shape_hit as u8));
-    }
-    None
 }
@@ -0,0 +1,334 @@
+use geometry::Vec3f;
+use std::sync::OnceLock;
+
+// Visible range covered by the spectral rendering, in nanometers
+pub const WAVELENGTH_MIN: f64 = 380.;
+pub const WAVELENGTH_MAX: f64 = 780.;
+
+// Step of the numerical integrations over the visible range
+const INTEGRATION_STEP: f64 = 1.;
+
+// Spectral power distribution, or spectral reflectance. Linear in between the samples,
+// constant past the first and the last one
+#[derive(Clone, Debug)]
+pub struct Spectrum {
+    pub samples: Vec<(f64, f64)>, // Wavelength in nanometers, value
+}
+
+fn piecewise_gaussian(wavelength: f64, mean: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let sigma = if wavelength < mean {
+        sigma_low
+    } else {
+        sigma_high
+    };
+    let t = (wavelength - mean) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 color matching functions, multi-lobe analytic fit
+// See "Simple analytic approximations to the CIE XYZ color matching functions",
+// Wyman, Sloan and Shirley, 2013
+pub fn cie_xyz(wavelength: f64) -> Vec3f {
+    let g = |mean, low, high| piecewise_gaussian(wavelength, mean, low, high);
+    Vec3f {
+        x: 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7)
+            - 0.065 * g(501.1, 20.4, 26.2),
+        y: 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        z: 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    }
+}
+
+// Sum of f over the visible range, times the wavelength step
+fn integrate<F: Fn(f64) -> Vec3f>(f: F) -> Vec3f {
+    let n = ((WAVELENGTH_MAX - WAVELENGTH_MIN) / INTEGRATION_STEP) as usize;
+    let mut sum = Vec3f::zero();
+    for k in 0..n {
+        sum += f(WAVELENGTH_MIN + (k as f64 + 0.5) * INTEGRATION_STEP);
+    }
+    sum.scaled(INTEGRATION_STEP)
+}
+
+// Integral of the luminance matching function, so that a flat spectrum of 1 gets Y = 1
+pub fn luminance_integral() -> f64 {
+    static INTEGRAL: OnceLock<f64> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| integrate(cie_xyz).y)
+}
+
+fn xyz_to_linear_srgb(xyz: Vec3f) -> Vec3f {
+    Vec3f {
+        x: 3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        y: -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        z: 0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    }
+}
+
+// XYZ to linear sRGB, white balanced so that a flat spectrum maps to a neutral grey,
+// the way the RGB scenes expect white lights and materials to look
+pub fn xyz_to_rgb(xyz: Vec3f) -> Vec3f {
+    static WHITE: OnceLock<Vec3f> = OnceLock::new();
+    let white = WHITE
+        .get_or_init(|| xyz_to_linear_srgb(integrate(cie_xyz).scaled(1. / luminance_integral())));
+
+    let rgb = xyz_to_linear_srgb(xyz);
+    Vec3f {
+        x: rgb.x / white.x,
+        y: rgb.y / white.y,
+        z: rgb.z / white.z,
+    }
+}
+
+// Smooth spectra covering the blue, green and red parts of the visible range,
+// adding up to a flat spectrum
+fn rgb_basis(wavelength: f64) -> Vec3f {
+    let step = |center: f64| 1. / (1. + (-(wavelength - center) / 10.).exp());
+    let (green_start, red_start) = (step(490.), step(590.));
+    Vec3f {
+        x: red_start,
+        y: green_start - red_start,
+        z: 1. - green_start,
+    }
+}
+
+// Inverse of a 3x3 matrix, given as its columns
+fn inverse(columns: [Vec3f; 3]) -> [Vec3f; 3] {
+    let [a, b, c] = columns;
+    let det = a.dot(b.cross(c));
+    let rows = [
+        b.cross(c).scaled(1. / det),
+        c.cross(a).scaled(1. / det),
+        a.cross(b).scaled(1. / det),
+    ];
+
+    // Rows of the inverse, transposed back to columns
+    [
+        Vec3f {
+            x: rows[0].x,
+            y: rows[1].x,
+            z: rows[2].x,
+        },
+        Vec3f {
+            x: rows[0].y,
+            y: rows[1].y,
+            z: rows[2].y,
+        },
+        Vec3f {
+            x: rows[0].z,
+            y: rows[1].z,
+            z: rows[2].z,
+        },
+    ]
+}
+
+// Weights of the basis spectra reproducing an RGB color
+fn basis_weights(rgb: Vec3f) -> Vec3f {
+    static TO_WEIGHTS: OnceLock<[Vec3f; 3]> = OnceLock::new();
+    let columns = TO_WEIGHTS.get_or_init(|| {
+        // Color of every basis spectrum
+        let column = |k: usize| {
+            let xyz = integrate(|l| cie_xyz(l).scaled(rgb_basis(l).channel(k)));
+            xyz_to_rgb(xyz.scaled(1. / luminance_integral()))
+        };
+        inverse([column(0), column(1), column(2)])
+    });
+
+    columns[0].scaled(rgb.x) + columns[1].scaled(rgb.y) + columns[2].scaled(rgb.z)
+}
+
+// Value at a given wavelength of a smooth spectrum with the given color. White gives
+// a flat spectrum, and the round trip back to RGB is exact within the reachable colors.
+// Very saturated colors would need negative values, these are clamped
+pub fn rgb_to_spectral(rgb: Vec3f, wavelength: f64) -> f64 {
+    basis_weights(rgb).dot(rgb_basis(wavelength)).max(0.)
+}
+
+// Black body spectral radiance, up to a constant factor
+fn planck(temperature: f64, wavelength: f64) -> f64 {
+    let (h, c, k) = (6.626_070_15e-34, 2.997_924_58e8, 1.380_649e-23);
+    let l = wavelength * 1e-9;
+    1. / (l.powi(5) * ((h * c / (l * k * temperature)).exp() - 1.))
+}
+
+impl Spectrum {
+    pub fn create(mut samples: Vec<(f64, f64)>) -> Spectrum {
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Spectrum { samples }
+    }
+
+    pub fn constant(value: f64) -> Spectrum {
+        Spectrum::create(vec![(WAVELENGTH_MIN, value)])
+    }
+
+    // Black body emitter, normalized to 1 at its peak
+    pub fn blackbody(temperature: f64) -> Spectrum {
+        // Wien's displacement law
+        let peak = planck(temperature, 2.897_771_955e6 / temperature);
+        let samples = (0..=80)
+            .map(|k| {
+                let l = WAVELENGTH_MIN + 5. * k as f64;
+                (l, planck(temperature, l) / peak)
+            })
+            .collect();
+        Spectrum::create(samples)
+    }
+
+    // Smooth spectrum with this color, every 5 nm
+    pub fn from_rgb(rgb: Vec3f) -> Spectrum {
+        let samples = (0..=80)
+            .map(|k| {
+                let l = WAVELENGTH_MIN + 5. * k as f64;
+                (l, rgb_to_spectral(rgb, l))
+            })
+            .collect();
+        Spectrum::create(samples)
+    }
+
+    pub fn eval(&self, wavelength: f64) -> f64 {
+        let samples = &self.samples;
+        if samples.is_empty() {
+            return 0.;
+        }
+
+        let next = samples.partition_point(|s| s.0 < wavelength);
+        if next == 0 {
+            return samples[0].1;
+        }
+        if next == samples.len() {
+            return samples[samples.len() - 1].1;
+        }
+
+        let (l0, v0) = samples[next - 1];
+        let (l1, v1) = samples[next];
+        v0 + (v1 - v0) * (wavelength - l0) / (l1 - l0)
+    }
+
+    // Product of two spectra, a light reflected by a material for instance
+    pub fn product(&self, other: &Spectrum) -> Spectrum {
+        let mut wavelengths: Vec<f64> = self
+            .samples
+            .iter()
+            .chain(other.samples.iter())
+            .map(|s| s.0)
+            .collect();
+        wavelengths.sort_by(|a, b| a.total_cmp(b));
+        wavelengths.dedup();
+
+        Spectrum::create(
+            wavelengths
+                .iter()
+                .map(|l| (*l, self.eval(*l) * other.eval(*l)))
+                .collect(),
+        )
+    }
+
+    // Y = 1 for a flat spectrum of 1
+    pub fn to_xyz(&self) -> Vec3f {
+        integrate(|l| cie_xyz(l).scaled(self.eval(l))).scaled(1. / luminance_integral())
+    }
+
+    pub fn to_rgb(&self) -> Vec3f {
+        xyz_to_rgb(self.to_xyz())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close(a: Vec3f, b: Vec3f, tolerance: f64) -> bool {
+        (a - b).norm() < tolerance
+    }
+
+    #[test]
+    fn test_color_matching() {
+        // Peaks of the matching functions, and the usual integral of y (106.9)
+        let xyz = cie_xyz(555.);
+        assert![(xyz.y - 1.).abs() < 0.02];
+        assert![cie_xyz(600.).x > 1. && cie_xyz(445.).z > 1.7];
+        assert![(luminance_integral() - 106.9).abs() < 1.];
+
+        // A flat spectrum is white, its chromaticity is the equal energy point
+        let white = Spectrum::constant(1.).to_xyz();
+        assert![(white.y - 1.).abs() < 1e-9];
+        let sum = white.x + white.y + white.z;
+        assert![(white.x / sum - 1. / 3.).abs() < 0.01];
+        assert![close(
+            Spectrum::constant(0.5).to_rgb(),
+            Vec3f::ones().scaled(0.5),
+            1e-9
+        )];
+
+        // Monochromatic lights in the expected parts of the gamut
+        let line = |l: f64| Spectrum::create(vec![(l - 5., 0.), (l, 1.), (l + 5., 0.)]).to_rgb();
+        let red = line(630.);
+        let blue = line(450.);
+        assert![red.x > red.y && red.x > red.z];
+        assert![blue.z > blue.x && blue.z > blue.y];
+    }
+
+    #[test]
+    fn test_rgb_upsampling() {
+        // White is flat, and the round trip gives the same color back
+        for l in [400., 500., 600., 700.].iter() {
+            assert![(rgb_to_spectral(Vec3f::ones(), *l) - 1.).abs() < 1e-9];
+        }
+        let colors = [
+            Vec3f {
+                x: 0.8,
+                y: 0.3,
+                z: 0.2,
+            },
+            Vec3f {
+                x: 0.3,
+                y: 0.9,
+                z: 0.9,
+            },
+            Vec3f {
+                x: 0.2,
+                y: 0.2,
+                z: 0.5,
+            },
+        ];
+        for color in colors.iter() {
+            let spectrum = Spectrum::from_rgb(*color);
+            assert![spectrum.samples.iter().all(|s| s.1 >= 0.)];
+            assert![close(spectrum.to_rgb(), *color, 0.01)];
+        }
+
+        // Red reflects the long wavelengths
+        let red = Spectrum::from_rgb(colors[0]);
+        assert![red.eval(650.) > red.eval(450.)];
+    }
+
+    #[test]
+    fn test_metamerism() {
+        // Narrow green and red lines over a grey floor, and their smooth look-alike:
+        // the same yellowish color under a flat light...
+        let line = |l: f64, floor: f64| vec![(l - 10., floor), (l, 1. + floor), (l + 10., floor)];
+        let lines = Spectrum::create([line(540., 0.2), line(610., 0.2)].concat());
+        let rgb = lines.to_rgb();
+        let metamer = Spectrum::from_rgb(rgb);
+        assert![close(metamer.to_rgb(), rgb, 1e-3)];
+
+        // ... but not under a narrow band light in between
+        let sodium = Spectrum::create(line(575., 0.));
+        let a = lines.product(&sodium).to_rgb();
+        let b = metamer.product(&sodium).to_rgb();
+        assert![b.norm() > 1.5 * a.norm()];
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let spectrum = Spectrum::create(vec![(600., 1.), (400., 0.)]);
+        assert_eq![spectrum.eval(300.), 0.];
+        assert_eq![spectrum.eval(500.), 0.5];
+        assert_eq![spectrum.eval(700.), 1.];
+        assert_eq![Spectrum::create(vec![]).eval(500.), 0.];
+
+        // Hot bodies are bluer
+        let cold = Spectrum::blackbody(2000.);
+        let hot = Spectrum::blackbody(10000.);
+        assert![cold.eval(700.) > cold.eval(400.)];
+        assert![hot.eval(400.) > hot.eval(700.)];
+        assert![(Spectrum::blackbody(5000.).eval(580.) - 1.).abs() < 0.01];
+    }
+}
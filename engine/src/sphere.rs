@@ -1,5 +1,6 @@
 use geometry::Vec3f;
 use shapes::*;
+use std::f64::consts::PI;
 
 // Our most basic shape: a simple sphere, easy to intersect
 #[derive(Debug, Clone)]
@@ -22,6 +23,13 @@ pub fn create(center: Vec3f, radius: f64, reflectance: Reflectance) -> Sphere {
     }
 }
 
+// Latitude / longitude mapping, u wraps around the y axis, v goes from the bottom pole to the top
+fn sphere_uv(normal: &Vec3f) -> (f64, f64) {
+    let u = 0.5 + normal.z.atan2(normal.x) / (2. * PI);
+    let v = 0.5 + normal.y.clamp(-1., 1.).asin() / PI;
+    (u, v)
+}
+
 // Sphere implements the Shape trait, you can intersect it
 impl Shape for Sphere {
     fn intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<Intersection> {
@@ -52,11 +60,14 @@ impl Shape for Sphere {
 
         // We've an intersection
         let intersection_point = *orig + dir.scaled(t0);
+        let normal = (intersection_point - self.center).normalized();
 
         Some(Intersection {
             point: intersection_point,
-            normal: (intersection_point - self.center).normalized(),
-            reflectance: self.reflectance,
+            normal,
+            reflectance: self.reflectance.clone(),
+            barycentric: None,
+            uv: Some(sphere_uv(&normal)),
         })
     }
 
@@ -64,3 +75,51 @@ impl Shape for Sphere {
         self.bounding_box.clone()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uv() {
+        let sphere = create(
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -5.,
+            },
+            1.,
+            Reflectance::create_default(),
+        );
+
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        let (u, v) = sphere.intersect(&Vec3f::zero(), &dir).unwrap().uv.unwrap();
+        assert![(u - 0.75).abs() < 1e-9];
+        assert![(v - 0.5).abs() < 1e-9];
+
+        // Poles
+        let top = Vec3f {
+            x: 0.,
+            y: 5.,
+            z: -5.,
+        };
+        let down = Vec3f {
+            x: 0.,
+            y: -1.,
+            z: 0.,
+        };
+        let (_, v) = sphere.intersect(&top, &down).unwrap().uv.unwrap();
+        assert![(v - 1.).abs() < 1e-9];
+        let bottom = Vec3f {
+            x: 0.,
+            y: -5.,
+            z: -5.,
+        };
+        let (_, v) = sphere.intersect(&bottom, &-down).unwrap().uv.unwrap();
+        assert![v.abs() < 1e-9];
+    }
+}
@@ -0,0 +1,327 @@
+extern crate png;
+
+use geometry::Vec3f;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+
+// Anything which can modulate a color over a surface, from its texture
+// coordinates (when the shape provides them) or from the hit point itself
+pub trait Texture: Debug + Send + Sync {
+    fn value(&self, uv: Option<(f64, f64)>, point: &Vec3f) -> Vec3f;
+}
+
+// An RGB image, sampled with texture coordinates in [0, 1] (repeated outside)
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Vec3f>, // Line by line, top line first
+}
+
+impl ImageTexture {
+    pub fn create(width: usize, height: usize, pixels: Vec<Vec3f>) -> ImageTexture {
+        assert![width > 0 && height > 0];
+        assert_eq![pixels.len(), width * height];
+
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn pixel(&self, i: i64, j: i64) -> Vec3f {
+        // Wrap around, the texture repeats itself
+        let i = i.rem_euclid(self.width as i64) as usize;
+        let j = j.rem_euclid(self.height as i64) as usize;
+        self.pixels[j * self.width + i]
+    }
+
+    // Bilinear interpolation in between the four closest pixels.
+    // v goes upwards, as in the .obj files
+    pub fn sample(&self, u: f64, v: f64) -> Vec3f {
+        // Continuous pixel coordinates, pixel centers are at half integers
+        let x = u * self.width as f64 - 0.5;
+        let y = (1. - v) * self.height as f64 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let dx = x - x0;
+        let dy = y - y0;
+        let (i, j) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(i, j).scaled(1. - dx) + self.pixel(i + 1, j).scaled(dx);
+        let bottom = self.pixel(i, j + 1).scaled(1. - dx) + self.pixel(i + 1, j + 1).scaled(dx);
+        top.scaled(1. - dy) + bottom.scaled(dy)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: Option<(f64, f64)>, _point: &Vec3f) -> Vec3f {
+        // No coordinates, no modulation
+        match uv {
+            Some((u, v)) => self.sample(u, v),
+            None => Vec3f::ones(),
+        }
+    }
+}
+
+// Load a .png or a .ppm file, depending on the extension
+pub fn load(path: &str) -> Result<ImageTexture, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let loaded = match extension.as_deref() {
+        Some("png") => load_png(path),
+        Some("ppm") => load_ppm(path),
+        _ => Err(String::from("Unsupported texture format")),
+    };
+    loaded.map_err(|e| format!("Could not load texture from {}: {}", path, e))
+}
+
+fn load_png(path: &str) -> Result<ImageTexture, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+
+    let mut buffer = vec![0; reader.output_buffer_size().ok_or("Image too big")?];
+    let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err(String::from("Unexpected indexed colors")),
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let pixels = (0..width * height)
+        .map(|p| {
+            let start = (p / width) * info.line_size + (p % width) * channels;
+            let value = |c: usize| buffer[start + c] as f64 / 255.;
+            if channels < 3 {
+                Vec3f::ones().scaled(value(0))
+            } else {
+                Vec3f {
+                    x: value(0),
+                    y: value(1),
+                    z: value(2),
+                }
+            }
+        })
+        .collect();
+
+    Ok(ImageTexture::create(width, height, pixels))
+}
+
+// Binary (P6) or ascii (P3) portable pixmaps
+fn load_ppm(path: &str) -> Result<ImageTexture, String> {
+    let mut data = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| e.to_string())?;
+
+    // Header: magic, width, height, max value, separated by whitespaces and comments
+    let mut position = 0;
+    let mut next_token = || -> Result<String, String> {
+        let mut token = String::new();
+        while position < data.len() {
+            let c = data[position] as char;
+            if c == '#' && token.is_empty() {
+                while position < data.len() && data[position] != b'\n' {
+                    position += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                if !token.is_empty() {
+                    break;
+                }
+            } else {
+                token.push(c);
+            }
+            position += 1;
+        }
+
+        if token.is_empty() {
+            return Err(String::from("Truncated file"));
+        }
+        Ok(token)
+    };
+
+    let parse = |token: String| token.parse::<usize>().map_err(|e| e.to_string());
+
+    let magic = next_token()?;
+    let width = parse(next_token()?)?;
+    let height = parse(next_token()?)?;
+    let max_value = parse(next_token()?)?;
+
+    // Three values per pixel, the header could ask for more than can be addressed
+    let size = width.checked_mul(height).and_then(|n| n.checked_mul(3));
+    let size = match size {
+        Some(size) if size > 0 && max_value > 0 && max_value <= 255 => size,
+        _ => return Err(String::from("Unsupported dimensions or depth")),
+    };
+
+    let values: Vec<usize> = match magic.as_str() {
+        "P3" => (0..size)
+            .map(|_| next_token().and_then(parse))
+            .collect::<Result<Vec<usize>, String>>()?,
+        "P6" => {
+            // A single whitespace separates the header from the binary data
+            let start = position + 1;
+            if data.len().saturating_sub(start) < size {
+                return Err(String::from("Truncated file"));
+            }
+            data[start..start + size]
+                .iter()
+                .map(|v| *v as usize)
+                .collect()
+        }
+        _ => return Err(format!("Unsupported PPM type {}", magic)),
+    };
+
+    let scale = 1. / max_value as f64;
+    let pixels = values
+        .chunks(3)
+        .map(|c| Vec3f {
+            x: c[0] as f64 * scale,
+            y: c[1] as f64 * scale,
+            z: c[2] as f64 * scale,
+        })
+        .collect();
+
+    Ok(ImageTexture::create(width, height, pixels))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use framebuffer;
+    use std::io::Write;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(name)
+            .into_os_string()
+            .into_string()
+            .unwrap()
+    }
+
+    fn checker() -> ImageTexture {
+        // 2x2, black and white
+        ImageTexture::create(
+            2,
+            2,
+            vec![Vec3f::zero(), Vec3f::ones(), Vec3f::ones(), Vec3f::zero()],
+        )
+    }
+
+    #[test]
+    fn test_bilinear_sampling() {
+        let texture = checker();
+
+        // Pixel centers
+        assert_eq![texture.sample(0.25, 0.75), Vec3f::zero()];
+        assert_eq![texture.sample(0.75, 0.75), Vec3f::ones()];
+        assert_eq![texture.sample(0.25, 0.25), Vec3f::ones()];
+
+        // Half way in between two pixels
+        assert![(texture.sample(0.5, 0.75) - Vec3f::ones().scaled(0.5)).squared_norm() < 1e-12];
+
+        // Repeated outside of [0, 1]
+        assert_eq![texture.sample(1.25, 0.75), texture.sample(0.25, 0.75)];
+        assert_eq![texture.sample(0.75, -0.25), texture.sample(0.75, 0.75)];
+    }
+
+    #[test]
+    fn test_load_ppm() {
+        // Binary, through the framebuffer
+        let mut fb = framebuffer::create_frame_buffer(3, 2);
+        fb.buffer[0][0] = Vec3f::ones();
+        fb.buffer[1][2] = Vec3f {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let path = temp_path("rusty_marcher_texture.ppm");
+        fb.write_ppm(&path).unwrap();
+
+        let texture = load(&path).unwrap();
+        assert_eq![(texture.width, texture.height), (3, 2)];
+        assert_eq![texture.pixel(0, 0), Vec3f::ones()];
+        assert_eq![texture.pixel(2, 1).x, 1.];
+        assert_eq![texture.pixel(1, 1), Vec3f::zero()];
+
+        // Ascii, with comments
+        let path = temp_path("rusty_marcher_texture_ascii.ppm");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"P3\n# A comment\n2 1\n10\n10 10 10\n0 5 0\n")
+            .unwrap();
+        let texture = load(&path).unwrap();
+        assert_eq![texture.pixel(0, 0), Vec3f::ones()];
+        assert_eq![texture.pixel(1, 0).y, 0.5];
+    }
+
+    #[test]
+    fn test_load_png() {
+        let path = temp_path("rusty_marcher_texture.png");
+        {
+            let file = File::create(&path).unwrap();
+            let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), 2, 1);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[255, 0, 0, 255, 0, 51, 255, 0])
+                .unwrap();
+        }
+
+        let texture = load(&path).unwrap();
+        assert_eq![(texture.width, texture.height), (2, 1)];
+        assert_eq![
+            texture.pixel(0, 0),
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: 0.
+            }
+        ];
+        assert_eq![
+            texture.pixel(1, 0),
+            Vec3f {
+                x: 0.,
+                y: 0.2,
+                z: 1.
+            }
+        ];
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert![load("../test_data/missing.png")
+            .err()
+            .is_some_and(|e| e.contains("missing.png"))];
+        assert![load("../test_data/cornell_box.obj").is_err()];
+
+        // Dimensions overflowing the pixel count
+        let path = temp_path("rusty_marcher_texture_huge.ppm");
+        let header = format!["P6\n{} {}\n255\n", usize::MAX / 2, 2];
+        File::create(&path)
+            .unwrap()
+            .write_all(header.as_bytes())
+            .unwrap();
+        assert_eq![
+            load_ppm(&path).err(),
+            Some(String::from("Unsupported dimensions or depth"))
+        ];
+        assert![load(&path).is_err()];
+    }
+}
@@ -7,11 +7,8 @@ pub struct Triangle {
     pub vertices: Vec<Vec3f>,
     pub normal: Vec3f,
     pub center: Vec3f,
-}
-
-// Vertices need to be defined counter-clockwise
-fn inside(a: Vec3f, p1: Vec3f, p2: Vec3f) -> bool {
-    (p1 - a).cross(p2 - a).z > 0.
+    pub vertex_normals: Option<Vec<Vec3f>>, // Smooth shading, interpolated over the face
+    pub texcoords: Option<Vec<(f64, f64)>>, // Per vertex, interpolated over the face
 }
 
 #[allow(dead_code)]
@@ -43,52 +40,117 @@ impl Triangle {
             vertices,
             normal: edge_1.cross(edge_2).normalized(),
             center: mean,
+            vertex_normals: None,
+            texcoords: None,
         }
     }
 
+    pub fn create_with_normals(vertices: Vec<Vec3f>, normals: Vec<Vec3f>) -> Triangle {
+        assert![normals.len() == 3];
+
+        let mut triangle = Triangle::create(vertices);
+        triangle.vertex_normals = Some(normals.iter().map(|n| n.normalized()).collect());
+        triangle
+    }
+
+    // Texture coordinates at the given barycentric coordinates
+    pub fn interpolate_texcoords(&self, barycentric: &Vec3f) -> Option<(f64, f64)> {
+        self.texcoords.as_ref().map(|uv| {
+            (
+                uv[0].0 * barycentric.x + uv[1].0 * barycentric.y + uv[2].0 * barycentric.z,
+                uv[0].1 * barycentric.x + uv[1].1 * barycentric.y + uv[2].1 * barycentric.z,
+            )
+        })
+    }
+
+    // Shading normal at the given barycentric coordinates
+    pub fn interpolate_normal(&self, barycentric: &Vec3f) -> Vec3f {
+        match self.vertex_normals {
+            Some(ref normals) => {
+                let normal = (normals[0].scaled(barycentric.x)
+                    + normals[1].scaled(barycentric.y)
+                    + normals[2].scaled(barycentric.z))
+                .normalized();
+
+                // Degenerate interpolation (opposite normals), fall back to the face
+                if normal.squared_norm() > 0. {
+                    normal
+                } else {
+                    self.normal
+                }
+            }
+            None => self.normal,
+        }
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        let mut bb = BoundingBox::create(self.vertices[0]);
+        bb.update(&self.vertices[1]);
+        bb.update(&self.vertices[2]);
+        bb
+    }
+
     pub fn intersect(&self, orig: &Vec3f, dir: &Vec3f) -> Option<Intersection> {
-        // Very similar to a polygon intersection, but we know that we only have 3 sides here
+        // Moller-Trumbore, independent of the triangle orientation and winding
+        // See https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
 
         // Direction needs to be normalized
         assert![(dir.squared_norm() - 1.).abs() < 1e-4];
 
+        let edge_1 = self.vertices[1] - self.vertices[0];
+        let edge_2 = self.vertices[2] - self.vertices[0];
+
         // Parallel to the plane
-        let dot_product = dir.dot(self.normal);
-        if dot_product.abs() < 1e-6 {
+        let p = dir.cross(edge_2);
+        let det = edge_1.dot(p);
+        if det.abs() < 1e-12 {
             return None;
         }
+        let inv_det = 1. / det;
 
-        // Compute the intersection point on the plane
-        let dist = (self.center - *orig).dot(self.normal) / dot_product;
+        // Barycentric coordinates of the hit on the triangle plane
+        let s = *orig - self.vertices[0];
+        let u = s.dot(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge_1);
+        let v = dir.dot(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
 
         // Going away
+        let dist = edge_2.dot(q) * inv_det;
         if dist < 0. {
             return None;
         }
 
-        let intersect = *orig + dir.scaled(dist);
-
-        // Does it lie within or outside of the convex polygon ?
-        for i in 0..3 {
-            if !inside(intersect, self.vertices[i], self.vertices[(i + 1) % 3]) {
-                return None;
-            }
-        }
+        let barycentric = Vec3f {
+            x: 1. - u - v,
+            y: u,
+            z: v,
+        };
 
         Some(Intersection {
-            point: intersect,
-            normal: self.normal,
+            point: *orig + dir.scaled(dist),
+            normal: self.interpolate_normal(&barycentric),
             reflectance: Reflectance::create_default(),
+            barycentric: Some(barycentric),
+            uv: self.interpolate_texcoords(&barycentric),
         })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test_intersect() {
         use super::*;
-        let vertices = vec![
+        let vertices = [
             Vec3f {
                 x: -1.,
                 y: 3.,
@@ -136,4 +198,242 @@ mod test {
         assert![(triangle1.normal.squared_norm() - 1.).abs() < 1e-3];
         assert![triangle1.normal.dot(dir) < 0.];
     }
+
+    // Faces perpendicular to the six axis directions, at distance 5 from the origin
+    fn axis_faces() -> Vec<(Vec3f, Vec<Vec3f>)> {
+        let axes = [
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        ];
+
+        let mut faces = vec![];
+        for i in 0..3 {
+            for sign in &[1., -1.] {
+                let n = axes[i].scaled(*sign);
+                let t1 = axes[(i + 1) % 3];
+                let t2 = axes[(i + 2) % 3];
+                let c = n.scaled(5.);
+                faces.push((
+                    n,
+                    vec![c - t1 - t2, c + t1.scaled(2.) - t2, c - t1 + t2.scaled(2.)],
+                ));
+            }
+        }
+        faces
+    }
+
+    #[test]
+    fn test_intersect_all_orientations() {
+        for (n, vertices) in axis_faces() {
+            let forward = Triangle::create(vertices.clone());
+            let backward = Triangle::create(vec![vertices[2], vertices[1], vertices[0]]);
+
+            for triangle in &[forward, backward] {
+                // Normal is perpendicular to the face, whatever the winding
+                assert![(triangle.normal.dot(n).abs() - 1.).abs() < 1e-6];
+
+                let dir = (n.scaled(5.)
+                    + Vec3f {
+                        x: 0.1,
+                        y: 0.2,
+                        z: 0.3,
+                    })
+                .normalized();
+
+                let hit = triangle.intersect(&Vec3f::zero(), &dir).unwrap();
+                assert![(hit.point.dot(n) - 5.).abs() < 1e-6];
+
+                // The barycentric coordinates sum up to one and give back the hit point
+                let b = hit.barycentric.unwrap();
+                assert![(b.x + b.y + b.z - 1.).abs() < 1e-6];
+                assert![b.x >= 0. && b.y >= 0. && b.z >= 0.];
+                let interpolated = triangle.vertices[0].scaled(b.x)
+                    + triangle.vertices[1].scaled(b.y)
+                    + triangle.vertices[2].scaled(b.z);
+                assert![(interpolated - hit.point).squared_norm() < 1e-8];
+
+                // Looking away, or besides the triangle
+                assert![triangle.intersect(&Vec3f::zero(), &-dir).is_none()];
+                let outside = (n.scaled(5.) + (vertices[1] - vertices[2]).scaled(2.)).normalized();
+                assert![triangle.intersect(&Vec3f::zero(), &outside).is_none()];
+            }
+        }
+    }
+
+    #[test]
+    fn test_barycentric_vertices() {
+        let triangle = Triangle::create(vec![
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: -1.,
+            },
+        ]);
+
+        let orig = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+
+        // Close to the first vertex, then to the second
+        let offset = Vec3f {
+            x: 1e-3,
+            y: 1e-3,
+            z: 0.,
+        };
+        let b = triangle
+            .intersect(&(orig + offset), &dir)
+            .unwrap()
+            .barycentric
+            .unwrap();
+        assert![b.x > 0.99];
+
+        let offset = Vec3f {
+            x: 1. - 2e-3,
+            y: 1e-3,
+            z: 0.,
+        };
+        let b = triangle
+            .intersect(&(orig + offset), &dir)
+            .unwrap()
+            .barycentric
+            .unwrap();
+        assert![b.y > 0.99];
+    }
+
+    #[test]
+    fn test_smooth_normals() {
+        let vertices = vec![
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: -1.,
+            },
+        ];
+
+        // Normals tilted towards the outside, as on a sphere
+        let normals = vec![
+            Vec3f {
+                x: -1.,
+                y: -1.,
+                z: 2.,
+            },
+            Vec3f {
+                x: 1.,
+                y: -1.,
+                z: 2.,
+            },
+            Vec3f {
+                x: -1.,
+                y: 1.,
+                z: 2.,
+            },
+        ];
+
+        let flat = Triangle::create(vertices.clone());
+        let smooth = Triangle::create_with_normals(vertices, normals.clone());
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+
+        // Close to a vertex, the normal is the vertex normal
+        let orig = Vec3f {
+            x: 1e-4,
+            y: 1e-4,
+            z: 0.,
+        };
+        let hit = smooth.intersect(&orig, &dir).unwrap();
+        assert![(hit.normal - normals[0].normalized()).squared_norm() < 1e-6];
+        assert_eq![flat.intersect(&orig, &dir).unwrap().normal, flat.normal];
+
+        // Half way on an edge, the normal is the average of its vertices
+        let orig = Vec3f {
+            x: 0.5,
+            y: 1e-6,
+            z: 0.,
+        };
+        let hit = smooth.intersect(&orig, &dir).unwrap();
+        let expected = (normals[0].normalized() + normals[1].normalized()).normalized();
+        assert![(hit.normal - expected).squared_norm() < 1e-6];
+        assert![(hit.normal.squared_norm() - 1.).abs() < 1e-9];
+    }
+
+    #[test]
+    fn test_texcoords() {
+        let mut triangle = Triangle::create(vec![
+            Vec3f {
+                x: 0.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 1.,
+                y: 0.,
+                z: -1.,
+            },
+            Vec3f {
+                x: 0.,
+                y: 1.,
+                z: -1.,
+            },
+        ]);
+
+        let orig = Vec3f {
+            x: 0.25,
+            y: 0.5,
+            z: 0.,
+        };
+        let dir = Vec3f {
+            x: 0.,
+            y: 0.,
+            z: -1.,
+        };
+        assert![triangle.intersect(&orig, &dir).unwrap().uv.is_none()];
+
+        triangle.texcoords = Some(vec![(0.5, 0.), (1., 0.), (0.5, 1.)]);
+        let (u, v) = triangle.intersect(&orig, &dir).unwrap().uv.unwrap();
+        assert![(u - 0.625).abs() < 1e-9];
+        assert![(v - 0.5).abs() < 1e-9];
+    }
 }